@@ -0,0 +1,54 @@
+use solana_program::program_error::ProgramError;
+use thiserror::Error;
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    #[error("Account is not owned by the expected program")]
+    InvalidAccountOwner,
+    #[error("Account must be writable")]
+    AccountNotWritable,
+    #[error("Invalid instruction data")]
+    InvalidInstruction,
+    #[error("Stream account is already initialized")]
+    AlreadyInitialized,
+    #[error("Stream curve parameters are out of range")]
+    InvalidCurve,
+    #[error("Flow rate must not be negative")]
+    InvalidFlowRate,
+    #[error("Accrued amount overflows")]
+    AccrualOverflow,
+    #[error("Stream has already been terminated")]
+    StreamTerminated,
+    #[error("Stream has not been terminated")]
+    StreamNotTerminated,
+    #[error("Settlement has already been acknowledged")]
+    AlreadyAcknowledged,
+    #[error("Stream account is not the expected program address")]
+    InvalidStreamAddress,
+    #[error("Clock sysvar is unavailable")]
+    ClockUnavailable,
+    #[error("Stream cannot be terminated under its cancel policy")]
+    TerminationNotPermitted,
+    #[error("Reserved balance exceeds the initial balance")]
+    InvalidReserve,
+    #[error("Streams cannot be merged")]
+    StreamsNotMergeable,
+    #[error("Combined balance overflows")]
+    BalanceOverflow,
+    #[error("Account does not match the one stored in the stream")]
+    InvalidAccount,
+    #[error("Throttle factor exceeds the full rate")]
+    InvalidThrottle,
+    #[error("Amount exceeds the balance that has not streamed yet")]
+    InsufficientUnstreamedBalance,
+    #[error("Billing period must not be negative")]
+    InvalidBillingPeriod,
+    #[error("Stream account is not initialized")]
+    NotInitialized,
+}
+
+impl From<StreamError> for ProgramError {
+    fn from(e: StreamError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
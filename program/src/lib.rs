@@ -5,8 +5,11 @@ use solana_program::{
     account_info::AccountInfo,
 };
 
+pub mod error;
+pub mod math;
 pub mod state;
 pub mod processor;
+pub mod time;
 
 entrypoint!(process_instruction);
 
@@ -0,0 +1,143 @@
+use crate::error::StreamError;
+use crate::state::FlowPeriod;
+
+/// Amount accrued at `flow_rate` units per second over `elapsed` seconds.
+/// Nothing accrues before the stream starts, so a negative `elapsed` yields zero.
+pub fn checked_accrual(flow_rate: i64, elapsed: i64) -> Result<u64, StreamError> {
+    let flow_rate = u64::try_from(flow_rate).map_err(|_| StreamError::InvalidFlowRate)?;
+    let elapsed = u64::try_from(elapsed).unwrap_or(0);
+    flow_rate
+        .checked_mul(elapsed)
+        .ok_or(StreamError::AccrualOverflow)
+}
+
+/// Balance needed to stream `flow_rate` units per `flow_period` for
+/// `duration_secs` seconds on a linear curve. A negative duration needs no
+/// funding.
+pub fn required_balance(
+    flow_rate: i64,
+    flow_period: FlowPeriod,
+    duration_secs: i64,
+) -> Result<u64, StreamError> {
+    flow_period.accrual(flow_rate, duration_secs)
+}
+
+/// First UTC midnight at or after `timestamp`, or `None` if it is past the
+/// last representable one.
+pub fn next_utc_midnight(timestamp: i64) -> Option<i64> {
+    const DAY: i64 = 86400;
+    match timestamp.rem_euclid(DAY) {
+        0 => Some(timestamp),
+        into_day => timestamp.checked_add(DAY - into_day),
+    }
+}
+
+/// Pays `accrued` out of `balance`, returning `(paid, remaining)`. The payout is
+/// capped at the balance, so `paid + remaining == balance` always holds.
+pub fn apply_accrual(balance: u64, accrued: u64) -> (u64, u64) {
+    let paid = accrued.min(balance);
+    (paid, balance - paid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checked_accrual() {
+        assert_eq!(checked_accrual(100, 10), Ok(1000));
+        assert_eq!(checked_accrual(0, i64::MAX), Ok(0));
+        assert_eq!(checked_accrual(i64::MAX, 0), Ok(0));
+        assert_eq!(checked_accrual(i64::MAX, 2), Ok(i64::MAX as u64 * 2));
+    }
+
+    #[test]
+    fn test_checked_accrual_before_start() {
+        assert_eq!(checked_accrual(100, -1), Ok(0));
+        assert_eq!(checked_accrual(100, i64::MIN), Ok(0));
+    }
+
+    #[test]
+    fn test_checked_accrual_rejects_negative_rate() {
+        assert_eq!(checked_accrual(-1, 10), Err(StreamError::InvalidFlowRate));
+        assert_eq!(
+            checked_accrual(i64::MIN, 0),
+            Err(StreamError::InvalidFlowRate)
+        );
+    }
+
+    #[test]
+    fn test_checked_accrual_overflow() {
+        assert_eq!(
+            checked_accrual(i64::MAX, 3),
+            Err(StreamError::AccrualOverflow)
+        );
+        assert_eq!(
+            checked_accrual(i64::MAX, i64::MAX),
+            Err(StreamError::AccrualOverflow)
+        );
+
+        // Largest elapsed time that still fits at this rate
+        let flow_rate = 1_000_000_000;
+        let max_elapsed = (u64::MAX / flow_rate as u64) as i64;
+        assert_eq!(
+            checked_accrual(flow_rate, max_elapsed),
+            Ok(flow_rate as u64 * max_elapsed as u64)
+        );
+        assert_eq!(
+            checked_accrual(flow_rate, max_elapsed + 1),
+            Err(StreamError::AccrualOverflow)
+        );
+    }
+
+    #[test]
+    fn test_apply_accrual() {
+        assert_eq!(apply_accrual(1000, 0), (0, 1000));
+        assert_eq!(apply_accrual(1000, 400), (400, 600));
+        assert_eq!(apply_accrual(1000, 1000), (1000, 0));
+        assert_eq!(apply_accrual(1000, 1001), (1000, 0));
+        assert_eq!(apply_accrual(0, 500), (0, 0));
+        assert_eq!(apply_accrual(u64::MAX, u64::MAX), (u64::MAX, 0));
+    }
+
+    #[test]
+    fn test_required_balance() {
+        assert_eq!(
+            required_balance(100, FlowPeriod::Second, 86400),
+            Ok(8_640_000)
+        );
+        assert_eq!(required_balance(100, FlowPeriod::Second, 0), Ok(0));
+        assert_eq!(required_balance(100, FlowPeriod::Second, -60), Ok(0));
+        assert_eq!(
+            required_balance(-1, FlowPeriod::Second, 60),
+            Err(StreamError::InvalidFlowRate)
+        );
+        // A year at the largest rate overflows
+        assert_eq!(
+            required_balance(i64::MAX, FlowPeriod::Second, 365 * 86400),
+            Err(StreamError::AccrualOverflow)
+        );
+    }
+
+    #[test]
+    fn test_required_balance_per_flow_period() {
+        // A day and a half at 100 per hour, and at 100 per day
+        assert_eq!(required_balance(100, FlowPeriod::Hour, 129_600), Ok(3600));
+        assert_eq!(required_balance(100, FlowPeriod::Day, 129_600), Ok(150));
+        // Partial periods are truncated like accrual
+        assert_eq!(required_balance(100, FlowPeriod::Hour, 35), Ok(0));
+    }
+
+    #[test]
+    fn test_next_utc_midnight() {
+        // 2024-01-01T00:00:00Z
+        let midnight = 1_704_067_200;
+        assert_eq!(next_utc_midnight(midnight), Some(midnight));
+        assert_eq!(next_utc_midnight(midnight + 1), Some(midnight + 86400));
+        assert_eq!(next_utc_midnight(midnight + 43200), Some(midnight + 86400));
+        assert_eq!(next_utc_midnight(midnight - 1), Some(midnight));
+        assert_eq!(next_utc_midnight(0), Some(0));
+        assert_eq!(next_utc_midnight(-1), Some(0));
+        assert_eq!(next_utc_midnight(i64::MAX), None);
+    }
+}
@@ -1,14 +1,23 @@
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
-    clock::Clock,
     entrypoint::ProgramResult,
+    log::sol_log_data,
     msg,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::ProgramError,
     pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, system_program,
     sysvar::Sysvar,
 };
 
-use crate::state::{StreamConfig, StreamInstruction};
+use crate::error::StreamError;
+use crate::math::{checked_accrual, next_utc_midnight};
+use crate::state::{
+    CancelPolicy, FlowPeriod, StreamAccountKind, StreamConfig, StreamCurve, StreamInstruction,
+    FULL_RATE_BPS, STREAM_CREATED_EVENT, STREAM_SEED,
+};
+use crate::time::{SysvarClock, TimeSource};
 use borsh::{BorshDeserialize, BorshSerialize};
 
 pub fn process_instruction(
@@ -16,22 +25,100 @@ pub fn process_instruction(
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    let instruction = StreamInstruction::try_from_slice(instruction_data)?;
+    process_instruction_with_time(program_id, accounts, instruction_data, &SysvarClock)
+}
+
+/// Processes an instruction, reading the current time from `time`.
+pub fn process_instruction_with_time(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    if instruction_data.is_empty() {
+        msg!("Instruction data is empty");
+        return Err(StreamError::InvalidInstruction.into());
+    }
+    // try_from_slice also rejects trailing bytes after a valid instruction
+    let instruction = StreamInstruction::try_from_slice(instruction_data).map_err(|e| {
+        msg!(
+            "Malformed instruction data ({} bytes): {}",
+            instruction_data.len(),
+            e
+        );
+        StreamError::InvalidInstruction
+    })?;
 
     match instruction {
         StreamInstruction::Initialize {
             flow_rate,
+            flow_period,
+            curve,
+            initial_balance,
+            account_kind,
+            cancel_policy,
+            reserved_balance,
+            snap_start_to_midnight,
+            emit_events,
+            billing_period,
+        } => process_initialize(
+            program_id,
+            accounts,
+            flow_rate,
+            flow_period,
+            curve,
             initial_balance,
-        } => process_initialize(program_id, accounts, flow_rate, initial_balance),
-        StreamInstruction::Terminate => process_terminate(program_id, accounts),
+            account_kind,
+            cancel_policy,
+            reserved_balance,
+            snap_start_to_midnight,
+            emit_events,
+            billing_period,
+            time,
+        ),
+        StreamInstruction::Terminate => process_terminate(program_id, accounts, time),
+        StreamInstruction::PreviewWithdraw => process_preview_withdraw(program_id, accounts, time),
+        StreamInstruction::Clone { initial_balance } => {
+            process_clone(program_id, accounts, initial_balance, time)
+        }
+        StreamInstruction::AcknowledgeSettlement => {
+            process_acknowledge_settlement(program_id, accounts, time)
+        }
+        StreamInstruction::Checkpoint => process_checkpoint(program_id, accounts, time),
+        StreamInstruction::MergeStreams => process_merge_streams(program_id, accounts, time),
+        StreamInstruction::Throttle { factor_bps } => {
+            process_throttle(program_id, accounts, factor_bps, time)
+        }
+        StreamInstruction::Projection => process_projection(program_id, accounts, time),
+        StreamInstruction::Clawback { amount } => {
+            process_clawback(program_id, accounts, amount, time)
+        }
+        StreamInstruction::CanWithdraw { amount } => {
+            process_can_withdraw(program_id, accounts, amount, time)
+        }
+        StreamInstruction::DeclineStream => process_decline_stream(program_id, accounts),
+        StreamInstruction::GetState => process_get_state(program_id, accounts, time),
+        StreamInstruction::SetReserve { amount } => {
+            process_set_reserve(program_id, accounts, amount, time)
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_initialize(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     flow_rate: i64,
+    flow_period: FlowPeriod,
+    curve: StreamCurve,
     initial_balance: u64,
+    account_kind: StreamAccountKind,
+    cancel_policy: CancelPolicy,
+    reserved_balance: u64,
+    snap_start_to_midnight: bool,
+    emit_events: bool,
+    billing_period: i64,
+    time: &dyn TimeSource,
 ) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
 
@@ -39,9 +126,9 @@ fn process_initialize(
     let sender = next_account_info(accounts_iter)?;
     let receiver = next_account_info(accounts_iter)?;
 
-    // Validate account ownership
-    if stream_account.owner != program_id {
-        return Err(ProgramError::IncorrectProgramId);
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
     }
 
     // Validate signer
@@ -49,30 +136,179 @@ fn process_initialize(
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    let bump = match account_kind {
+        StreamAccountKind::Keypair => {
+            if !stream_account.is_signer {
+                msg!("Keypair stream accounts must sign Initialize");
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            0
+        }
+        StreamAccountKind::ProgramAddress => {
+            let (address, bump) = StreamConfig::find_address(program_id, sender.key, receiver.key);
+            if *stream_account.key != address {
+                msg!("Stream account is not the program address for this sender and receiver");
+                return Err(StreamError::InvalidStreamAddress.into());
+            }
+
+            let bump_seed = [bump];
+            let signer_seeds: &[&[u8]] = &[
+                STREAM_SEED,
+                sender.key.as_ref(),
+                receiver.key.as_ref(),
+                &bump_seed,
+            ];
+
+            // An account with no lamports does not exist yet
+            if stream_account.lamports() == 0 {
+                let system_program_account = next_account_info(accounts_iter)?;
+                let rent = Rent::get()?;
+                invoke_signed(
+                    &system_instruction::create_account(
+                        sender.key,
+                        stream_account.key,
+                        rent.minimum_balance(StreamConfig::LEN),
+                        StreamConfig::LEN as u64,
+                        program_id,
+                    ),
+                    &[
+                        sender.clone(),
+                        stream_account.clone(),
+                        system_program_account.clone(),
+                    ],
+                    &[signer_seeds],
+                )?;
+            } else if system_program::check_id(stream_account.owner) {
+                // Anyone can send lamports to the address first, which makes
+                // create_account fail, so fund the rest and take it over
+                let system_program_account = next_account_info(accounts_iter)?;
+                let rent = Rent::get()?;
+                let shortfall = rent
+                    .minimum_balance(StreamConfig::LEN)
+                    .saturating_sub(stream_account.lamports());
+                if shortfall > 0 {
+                    invoke(
+                        &system_instruction::transfer(sender.key, stream_account.key, shortfall),
+                        &[
+                            sender.clone(),
+                            stream_account.clone(),
+                            system_program_account.clone(),
+                        ],
+                    )?;
+                }
+                invoke_signed(
+                    &system_instruction::allocate(stream_account.key, StreamConfig::LEN as u64),
+                    &[stream_account.clone(), system_program_account.clone()],
+                    &[signer_seeds],
+                )?;
+                invoke_signed(
+                    &system_instruction::assign(stream_account.key, program_id),
+                    &[stream_account.clone(), system_program_account.clone()],
+                    &[signer_seeds],
+                )?;
+            }
+            bump
+        }
+    };
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    // Initializing over a live stream would rewrite its terms, including a
+    // cancel policy that rules out ending it
+    if stream_account.data.borrow().iter().any(|byte| *byte != 0) {
+        return Err(StreamError::AlreadyInitialized.into());
+    }
+
+    // Accrual within a single period must fit in a u64
+    if let Err(e) = checked_accrual(flow_rate, flow_period.seconds()) {
+        msg!(
+            "Flow rate out of range: {} per {:?}",
+            flow_rate,
+            flow_period
+        );
+        return Err(e.into());
+    }
+    if reserved_balance > initial_balance {
+        msg!(
+            "Reserve {} exceeds initial balance {}",
+            reserved_balance,
+            initial_balance
+        );
+        return Err(StreamError::InvalidReserve.into());
+    }
+    if billing_period < 0 {
+        msg!("Billing period must not be negative: {}", billing_period);
+        return Err(StreamError::InvalidBillingPeriod.into());
+    }
+    if let Err(e) = curve.validate(flow_rate, flow_period) {
+        msg!("Invalid stream curve: {:?}", curve);
+        return Err(e.into());
+    }
+
     // Get current timestamp for stream start
-    let start_time = Clock::get()?.unix_timestamp;
+    let created_at = time.now()?;
+    let mut start_time = created_at;
+    if snap_start_to_midnight {
+        start_time = next_utc_midnight(start_time).ok_or(ProgramError::ArithmeticOverflow)?;
+    }
 
     // Create and initialize the stream
-    let stream = StreamConfig::initialize(
-        *sender.key,
-        *receiver.key,
-        flow_rate,
-        initial_balance,
-        start_time,
-    );
+    let stream = StreamConfig {
+        cancel_policy,
+        reserved_balance,
+        account_kind,
+        bump,
+        emit_events,
+        billing_period,
+        created_at,
+        ..StreamConfig::initialize(
+            *sender.key,
+            *receiver.key,
+            flow_rate,
+            flow_period,
+            curve,
+            initial_balance,
+            start_time,
+        )
+    };
 
     // Serialize and store the stream data
     stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+    log_stream_created(stream_account.key, &stream);
 
     msg!(
-        "Stream initialized: flow_rate={}, initial_balance={}",
+        "Stream initialized: flow_rate={} per {:?}, initial_balance={}, start_time={}, created_at={}",
         flow_rate,
-        initial_balance
+        flow_period,
+        initial_balance,
+        start_time,
+        created_at
     );
     Ok(())
 }
 
-fn process_terminate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+/// Emits the discovery event described at `STREAM_CREATED_EVENT`, unless the
+/// stream has events turned off.
+fn log_stream_created(stream_key: &Pubkey, stream: &StreamConfig) {
+    if !stream.emit_events {
+        return;
+    }
+    sol_log_data(&[
+        STREAM_CREATED_EVENT,
+        stream.sender.as_ref(),
+        stream.receiver.as_ref(),
+        stream_key.as_ref(),
+        &stream.created_at.to_le_bytes(),
+    ]);
+}
+
+fn process_terminate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
 
     let stream_account = next_account_info(accounts_iter)?;
@@ -83,285 +319,2640 @@ fn process_terminate(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramRe
     if stream_account.owner != program_id {
         return Err(ProgramError::IncorrectProgramId);
     }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
 
     // Deserialize the stream data
-    let mut stream = StreamConfig::try_from_slice(&stream_account.data.borrow())?;
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
 
-    // Verify either sender or receiver signed
-    if !((sender.is_signer && stream.sender == *sender.key)
-        || (receiver.is_signer && stream.receiver == *receiver.key))
-    {
-        return Err(ProgramError::MissingRequiredSignature);
+    // The passed wallets must be the stream's parties before signatures count
+    if stream.sender != *sender.key {
+        msg!("Sender account {} does not match the stream", sender.key);
+        return Err(StreamError::InvalidAccount.into());
+    }
+    if stream.receiver != *receiver.key {
+        msg!(
+            "Receiver account {} does not match the stream",
+            receiver.key
+        );
+        return Err(StreamError::InvalidAccount.into());
     }
 
-    // Calculate streamed amount
-    let current_time = Clock::get()?.unix_timestamp;
-    let time_elapsed = current_time - stream.start_time;
-    let amount_streamed = (time_elapsed * stream.flow_rate) as u64;
-
-    // Update balance
-    if amount_streamed > stream.static_balance {
-        stream.static_balance = 0;
+    // Verify a party allowed by the cancel policy signed
+    if stream.cancel_policy == CancelPolicy::None {
+        msg!("Stream cannot be terminated under its cancel policy");
+        return Err(StreamError::TerminationNotPermitted.into());
+    }
+    let policy = stream.cancel_policy;
+    let (terminated_by, signer) = if policy.sender_may_cancel() && sender.is_signer {
+        ("sender", sender)
+    } else if policy.receiver_may_cancel() && receiver.is_signer {
+        ("receiver", receiver)
     } else {
-        stream.static_balance -= amount_streamed;
+        return Err(ProgramError::MissingRequiredSignature);
+    };
+    // The signing party must be a plain wallet owned by the system program.
+    // The other party is only matched by key, as Initialize accepts a
+    // receiver with any owner and it must not be able to block termination.
+    if !system_program::check_id(signer.owner) {
+        msg!(
+            "Terminating {} account must be owned by the system program",
+            terminated_by
+        );
+        return Err(StreamError::InvalidAccountOwner.into());
+    }
+
+    // Settling twice would deduct the streamed amount again
+    if stream.terminated {
+        return Err(StreamError::StreamTerminated.into());
     }
 
+    // Deduct the streamed amount from the balance; the reserve stays behind
+    let current_time = time.now()?;
+    let owed = stream.available_balance(current_time)?;
+    stream.static_balance -= owed;
+    stream.settled_balance = stream.settled_balance.saturating_add(owed);
+    stream.terminated = true;
+
     // Save updated stream data
     stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
 
     msg!(
         "Stream terminated by {}: remaining_balance={}",
-        if sender.is_signer {
-            "sender"
-        } else {
-            "receiver"
-        },
+        terminated_by,
         stream.static_balance
     );
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use solana_program::{clock::Clock, clock::Epoch};
-    use std::mem;
+fn process_preview_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
 
-    pub struct Test;
+    let stream_account = next_account_info(accounts_iter)?;
 
-    impl Test {
-        pub fn get_clock() -> Clock {
-            Clock {
-                slot: 1,
-                epoch_start_timestamp: 1,
-                epoch: 1,
-                leader_schedule_epoch: 1,
-                unix_timestamp: 1000,
-            }
-        }
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-        pub fn time_warp(seconds_to_advance: i64) -> Clock {
-            let base_timestamp = 1000;
-            Clock {
-                slot: 1000,
-                epoch_start_timestamp: 1,
-                epoch: 1,
-                leader_schedule_epoch: 1,
-                unix_timestamp: base_timestamp + seconds_to_advance,
-            }
-        }
+    // Read-only: the stream account is never written here
+    let stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+    let current_time = time.now()?;
 
-        pub const ONE_HOUR: i64 = 3600;
-        pub const ONE_DAY: i64 = 86400;
-        pub const ONE_WEEK: i64 = 86400 * 7;
-        pub const ONE_MONTH: i64 = 86400 * 30;
+    set_return_data(&stream.available_balance(current_time)?.to_le_bytes());
+    Ok(())
+}
+
+fn process_can_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    #[test]
-    fn test_initialization() {
-        let program_id = Pubkey::default();
-        let sender_key = Pubkey::default();
-        let receiver_key = Pubkey::new_unique();
+    // Read-only: the stream account is never written here
+    let stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+    let current_time = time.now()?;
 
-        // Create the stream account
-        let mut stream_lamports = 0;
-        let mut stream_data = vec![0; mem::size_of::<StreamConfig>()];
-        let owner = program_id;
-        let binding = Pubkey::new_unique();
-        let stream_account = AccountInfo::new(
-            &binding,
-            false,
-            true,
-            &mut stream_lamports,
-            &mut stream_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        let mut sender_lamports = 0;
-        let mut sender_data = vec![];
-        let sender_account = AccountInfo::new(
-            &sender_key,
-            true,
-            false,
-            &mut sender_lamports,
-            &mut sender_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        let mut receiver_lamports = 0;
-        let mut receiver_data = vec![];
-        let receiver_account = AccountInfo::new(
-            &receiver_key,
-            false,
-            false,
-            &mut receiver_lamports,
-            &mut receiver_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        let accounts = vec![stream_account, sender_account, receiver_account];
-
-        let init_instr = StreamInstruction::Initialize {
-            flow_rate: 100,
-            initial_balance: 1000,
-        };
+    let payable = amount <= stream.available_balance(current_time)?;
+    set_return_data(&[payable as u8]);
+    Ok(())
+}
 
-        let mut instr_data = vec![];
-        init_instr.serialize(&mut instr_data).unwrap();
+fn process_get_state(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
 
-        // Mock the Clock for our test
-        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {
-            clock: Test::get_clock(),
-        }));
+    let stream_account = next_account_info(accounts_iter)?;
 
-        assert_eq!(
-            process_instruction(&program_id, &accounts, &instr_data),
-            Ok(())
-        );
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-        let stream = StreamConfig::try_from_slice(&accounts[0].data.borrow()).unwrap();
-        println!("flow rate {}", &stream.flow_rate);
-        assert_eq!(stream.flow_rate, 100);
-        println!("static balance {}", &stream.static_balance);
-        assert_eq!(stream.static_balance, 1000);
-        println!("sender {}", &stream.sender);
-        assert_eq!(stream.sender, sender_key);
-        println!("receiver {}", &stream.receiver);
-        assert_eq!(stream.receiver, receiver_key);
-        println!("start time {}", &stream.start_time);
-        assert_eq!(stream.start_time, 1000); // Should match our mocked timestamp
+    // Read-only: the stream account is never written here
+    let stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+    let current_time = time.now()?;
+
+    set_return_data(&borsh::to_vec(&stream.summary(current_time)?)?);
+    Ok(())
+}
+
+fn process_projection(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    #[test]
-    fn test_termination() {
-        let program_id = Pubkey::default();
-        let sender_key = Pubkey::default();
-        let receiver_key = Pubkey::new_unique();
+    let stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+    let current_time = time.now()?;
 
-        let mut stream_lamports = 0;
-        let mut stream_data = vec![0; mem::size_of::<StreamConfig>()];
-        let owner = program_id;
-        let binding = Pubkey::new_unique();
-        let stream_account = AccountInfo::new(
-            &binding,
-            false,
-            true,
-            &mut stream_lamports,
-            &mut stream_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        let mut sender_lamports = 0;
-        let mut sender_data = vec![];
-        let mut sender_account = AccountInfo::new(
-            &sender_key,
-            true,
-            false,
-            &mut sender_lamports,
-            &mut sender_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        let mut receiver_lamports = 0;
-        let mut receiver_data = vec![];
-        let mut receiver_account = AccountInfo::new(
-            &receiver_key,
-            false,
-            false,
-            &mut receiver_lamports,
-            &mut receiver_data,
-            &owner,
-            false,
-            Epoch::default(),
-        );
-
-        // Initialize the stream
-        {
-            let init_accounts = vec![
-                stream_account.clone(),
-                sender_account.clone(),
-                receiver_account.clone(),
-            ];
+    let depletion_time = if stream.terminated {
+        None
+    } else {
+        stream.depletion_time(current_time)
+    };
+    set_return_data(&depletion_time.unwrap_or(i64::MAX).to_le_bytes());
+    Ok(())
+}
 
-            let init_instr = StreamInstruction::Initialize {
-                flow_rate: 100,
-                initial_balance: 1000,
-            };
+fn process_clone(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    initial_balance: u64,
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let template_account = next_account_info(accounts_iter)?;
+    let stream_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
 
-            solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {
-                clock: Test::get_clock(),
-            }));
+    // Validate account ownership
+    if template_account.owner != program_id || stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
 
-            let mut init_data = vec![];
-            init_instr.serialize(&mut init_data).unwrap();
+    // Like a keypair account at Initialize, the new account must sign so that
+    // nobody can claim an account someone else allocated
+    if !stream_account.is_signer {
+        msg!("New stream account must sign Clone");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
 
-            assert_eq!(
-                process_instruction(&program_id, &init_accounts, &init_data),
-                Ok(())
-            );
-        }
+    // The new account must be a fresh one, not the template or another live stream
+    if template_account.key == stream_account.key
+        || stream_account.data.borrow().iter().any(|byte| *byte != 0)
+    {
+        return Err(StreamError::AlreadyInitialized.into());
+    }
 
-        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {
-            clock: Test::time_warp(Test::ONE_DAY),
-        }));
+    let template = StreamConfig::unpack(&template_account.data.borrow())?;
+    template.verify_address(program_id, template_account.key)?;
 
-        sender_account.is_signer = false;
-        receiver_account.is_signer = true;
+    // Only the template's sender may fund a copy of it
+    if !sender.is_signer || template.sender != *sender.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
 
-        // Terminate the stream
-        {
-            let term_accounts = vec![
-                stream_account.clone(),
-                sender_account.clone(),
-                receiver_account.clone(),
-            ];
+    let start_time = time.now()?;
 
-            let term_instr = StreamInstruction::Terminate;
-            let mut term_data = vec![];
-            term_instr.serialize(&mut term_data).unwrap();
+    let stream = StreamConfig {
+        cancel_policy: template.cancel_policy,
+        emit_events: template.emit_events,
+        billing_period: template.billing_period,
+        ..StreamConfig::initialize(
+            template.sender,
+            template.receiver,
+            template.flow_rate,
+            template.flow_period,
+            template.curve,
+            initial_balance,
+            start_time,
+        )
+    };
 
-            assert_eq!(
-                process_instruction(&program_id, &term_accounts, &term_data),
-                Ok(())
-            );
-        }
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+    log_stream_created(stream_account.key, &stream);
 
-        let stream = StreamConfig::try_from_slice(&stream_account.data.borrow()).unwrap();
-        println!("flow rate term {}", &stream.flow_rate);
-        assert_eq!(stream.flow_rate, 100);
-        println!("static balance term {}", &stream.static_balance);
-        assert_eq!(stream.static_balance, 0);
-        println!("sender term {}", &stream.sender);
-        assert_eq!(stream.sender, sender_key);
-        println!("receiver term {}", &stream.receiver);
-        assert_eq!(stream.receiver, receiver_key);
-        println!("start term time {}", &stream.start_time);
-        assert_eq!(stream.start_time, 1000);
+    msg!(
+        "Stream cloned from {}: flow_rate={} per {:?}, initial_balance={}, created_at={}",
+        template_account.key,
+        stream.flow_rate,
+        stream.flow_period,
+        initial_balance,
+        stream.created_at
+    );
+    Ok(())
+}
+
+fn process_acknowledge_settlement(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let receiver = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    if !receiver.is_signer || stream.receiver != *receiver.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !stream.terminated {
+        return Err(StreamError::StreamNotTerminated.into());
     }
+    if stream.acknowledged {
+        return Err(StreamError::AlreadyAcknowledged.into());
+    }
+
+    stream.acknowledged = true;
+    stream.acknowledged_at = time.now()?;
+
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Settlement acknowledged by {} at {}",
+        receiver.key,
+        stream.acknowledged_at
+    );
+    Ok(())
 }
 
-#[cfg(test)]
-pub struct TestSyscallStubs {
-    clock: Clock,
+fn process_checkpoint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let party = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    // Only the parties may spend slots, otherwise anyone could evict the history
+    if !party.is_signer || (stream.sender != *party.key && stream.receiver != *party.key) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    stream.record_checkpoint(time.now()?)?;
+
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+
+    msg!("Checkpoint {} recorded", stream.checkpoints_written);
+    Ok(())
 }
 
-#[cfg(test)]
-impl solana_program::program_stubs::SyscallStubs for TestSyscallStubs {
-    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
-        unsafe {
-            *(var_addr as *mut Clock) = self.clock.clone();
-        }
-        0
+fn process_merge_streams(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let kept_account = next_account_info(accounts_iter)?;
+    let closed_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if kept_account.owner != program_id || closed_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !kept_account.is_writable || !closed_account.is_writable || !sender.is_writable {
+        msg!("Both streams and the sender must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+    // Merging a stream into itself would double its balance
+    if kept_account.key == closed_account.key {
+        msg!("Cannot merge a stream into itself");
+        return Err(StreamError::StreamsNotMergeable.into());
+    }
+
+    let mut kept = StreamConfig::unpack(&kept_account.data.borrow())?;
+    kept.verify_address(program_id, kept_account.key)?;
+    let mut closed = StreamConfig::unpack(&closed_account.data.borrow())?;
+    closed.verify_address(program_id, closed_account.key)?;
+
+    if !sender.is_signer || kept.sender != *sender.key || closed.sender != *sender.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if kept.terminated || closed.terminated {
+        return Err(StreamError::StreamTerminated.into());
+    }
+    // Rates only add up on the same linear schedule, and the merged stream
+    // must not loosen the receiver's protection against cancellation
+    if kept.receiver != closed.receiver
+        || kept.flow_period != closed.flow_period
+        || kept.curve != StreamCurve::Linear
+        || closed.curve != StreamCurve::Linear
+        || kept.cancel_policy != closed.cancel_policy
+        || kept.throttle_bps != closed.throttle_bps
+        || kept.billing_period != closed.billing_period
+    {
+        msg!("Streams must share receiver, flow period, a linear curve, cancel policy, throttle and billing period");
+        return Err(StreamError::StreamsNotMergeable.into());
+    }
+
+    // Settle both streams up to now. They restart together, so each must
+    // resume from the same point of its billing period
+    let current_time = time.now()?;
+    let restart_time = kept.restart_time(current_time);
+    if closed.restart_time(current_time) != restart_time {
+        msg!("Streams are at different points of their billing periods");
+        return Err(StreamError::StreamsNotMergeable.into());
+    }
+    let kept_owed = kept.available_balance(current_time)?;
+    let closed_owed = closed.available_balance(current_time)?;
+    kept.static_balance -= kept_owed;
+    closed.static_balance -= closed_owed;
+
+    let flow_rate = kept
+        .flow_rate
+        .checked_add(closed.flow_rate)
+        .ok_or(StreamError::AccrualOverflow)?;
+    if let Err(e) = checked_accrual(flow_rate, kept.flow_period.seconds()) {
+        msg!(
+            "Merged flow rate out of range: {} per {:?}",
+            flow_rate,
+            kept.flow_period
+        );
+        return Err(e.into());
+    }
+    kept.flow_rate = flow_rate;
+    kept.static_balance = kept
+        .static_balance
+        .checked_add(closed.static_balance)
+        .ok_or(StreamError::BalanceOverflow)?;
+    kept.reserved_balance = kept
+        .reserved_balance
+        .checked_add(closed.reserved_balance)
+        .ok_or(StreamError::BalanceOverflow)?;
+    kept.settled_balance = [kept_owed, closed.settled_balance, closed_owed]
+        .into_iter()
+        .try_fold(kept.settled_balance, u64::checked_add)
+        .ok_or(StreamError::BalanceOverflow)?;
+    kept.start_time = restart_time;
+
+    kept.serialize(&mut &mut kept_account.data.borrow_mut()[..])?;
+
+    // Close the second stream, returning its rent to the sender
+    let refund = closed_account.lamports();
+    **sender.lamports.borrow_mut() = sender
+        .lamports()
+        .checked_add(refund)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **closed_account.lamports.borrow_mut() = 0;
+    closed_account.data.borrow_mut().fill(0);
+
+    msg!(
+        "Merged {} into {}: flow_rate={} per {:?}, balance={}",
+        closed_account.key,
+        kept_account.key,
+        kept.flow_rate,
+        kept.flow_period,
+        kept.static_balance
+    );
+    Ok(())
+}
+
+fn process_throttle(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    factor_bps: u16,
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+    if factor_bps > FULL_RATE_BPS {
+        msg!("Throttle factor {} exceeds {}", factor_bps, FULL_RATE_BPS);
+        return Err(StreamError::InvalidThrottle.into());
+    }
+
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    if !sender.is_signer || stream.sender != *sender.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if stream.terminated {
+        return Err(StreamError::StreamTerminated.into());
+    }
+    // Cutting the rate is a partial cancellation
+    if !stream.cancel_policy.sender_may_cancel() {
+        msg!("Sender may not throttle under the stream's cancel policy");
+        return Err(StreamError::TerminationNotPermitted.into());
+    }
+    // Restarting any other curve would reset its schedule
+    if stream.curve != StreamCurve::Linear {
+        msg!("Only linear streams can be throttled");
+        return Err(StreamError::InvalidCurve.into());
+    }
+
+    // Settle at the old rate, then restart at the new one
+    let current_time = time.now()?;
+    let owed = stream.available_balance(current_time)?;
+    stream.static_balance -= owed;
+    stream.settled_balance = stream.settled_balance.saturating_add(owed);
+    stream.start_time = stream.restart_time(current_time);
+    stream.throttle_bps = factor_bps;
+
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Stream throttled to {} bps, balance={}",
+        factor_bps,
+        stream.static_balance
+    );
+    Ok(())
+}
+
+fn process_clawback(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    if !sender.is_signer || stream.sender != *sender.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if stream.terminated {
+        return Err(StreamError::StreamTerminated.into());
+    }
+    // Shortening the stream is a partial cancellation
+    if !stream.cancel_policy.sender_may_cancel() {
+        msg!("Sender may not claw back under the stream's cancel policy");
+        return Err(StreamError::TerminationNotPermitted.into());
+    }
+
+    // Only what has not streamed yet; the receiver's payable amount stays
+    let current_time = time.now()?;
+    let unstreamed = stream.streamable_balance() - stream.available_balance(current_time)?;
+    if amount > unstreamed {
+        msg!(
+            "Clawback of {} exceeds the unstreamed balance {}",
+            amount,
+            unstreamed
+        );
+        return Err(StreamError::InsufficientUnstreamedBalance.into());
+    }
+    stream.static_balance -= amount;
+
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+
+    msg!("Clawed back {}, balance={}", amount, stream.static_balance);
+    Ok(())
+}
+
+fn process_decline_stream(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
+    let receiver = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable || !sender.is_writable {
+        msg!("Stream and sender accounts must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+
+    let stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    if stream.sender != *sender.key {
+        msg!("Sender account {} does not match the stream", sender.key);
+        return Err(StreamError::InvalidAccount.into());
+    }
+    if !receiver.is_signer || stream.receiver != *receiver.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // A settled stream is kept as the record of its payout
+    if stream.terminated {
+        return Err(StreamError::StreamTerminated.into());
+    }
+
+    // Close the stream, returning everything in it to the sender
+    let refund = stream_account.lamports();
+    **sender.lamports.borrow_mut() = sender
+        .lamports()
+        .checked_add(refund)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **stream_account.lamports.borrow_mut() = 0;
+    stream_account.data.borrow_mut().fill(0);
+
+    msg!(
+        "Stream declined by {}: refunded {} lamports to {}",
+        receiver.key,
+        refund,
+        sender.key
+    );
+    Ok(())
+}
+
+fn process_set_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    time: &dyn TimeSource,
+) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter();
+
+    let stream_account = next_account_info(accounts_iter)?;
+    let sender = next_account_info(accounts_iter)?;
+
+    // Validate account ownership
+    if stream_account.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !stream_account.is_writable {
+        msg!("Stream account must be writable");
+        return Err(StreamError::AccountNotWritable.into());
+    }
+
+    let mut stream = StreamConfig::unpack(&stream_account.data.borrow())?;
+    stream.verify_address(program_id, stream_account.key)?;
+
+    if !sender.is_signer || stream.sender != *sender.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if stream.terminated {
+        return Err(StreamError::StreamTerminated.into());
+    }
+    if amount > stream.static_balance {
+        msg!(
+            "Reserve {} exceeds balance {}",
+            amount,
+            stream.static_balance
+        );
+        return Err(StreamError::InvalidReserve.into());
+    }
+
+    if amount > stream.reserved_balance {
+        // Growing the reserve is a partial cancellation
+        if !stream.cancel_policy.sender_may_cancel() {
+            msg!("Sender may not raise the reserve under the stream's cancel policy");
+            return Err(StreamError::TerminationNotPermitted.into());
+        }
+        let payable = stream.available_balance(time.now()?)?;
+        if stream.static_balance - amount < payable {
+            msg!(
+                "Reserve {} would hold back {} already payable",
+                amount,
+                payable
+            );
+            return Err(StreamError::InsufficientUnstreamedBalance.into());
+        }
+    }
+    stream.reserved_balance = amount;
+
+    stream.serialize(&mut &mut stream_account.data.borrow_mut()[..])?;
+
+    msg!("Reserve set to {}", amount);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{Checkpoint, StreamStatus, StreamSummary};
+    use crate::time::FixedTime;
+    use solana_program::{clock::Epoch, instruction::Instruction, program::get_return_data};
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    // Syscall stubs are process-global, so tests that install them must not overlap
+    static STUBS_LOCK: Mutex<()> = Mutex::new(());
+
+    /// A recorded CPI and the signer seeds it was invoked with.
+    type Invocation = (Instruction, Vec<Vec<Vec<u8>>>);
+
+    /// Captures return data and CPIs, which the default stubs drop, and
+    /// serves the default rent. Of a system program CPI only the owner change
+    /// is applied. Also captures `sol_log_data` events. Clones share the
+    /// captured state, so a test can keep one to inspect.
+    #[derive(Clone)]
+    pub struct TestSyscallStubs {
+        return_data: Arc<Mutex<Option<Vec<u8>>>>,
+        invoked: Arc<Mutex<Vec<Invocation>>>,
+        logged_data: Arc<Mutex<Vec<Vec<Vec<u8>>>>>,
+    }
+
+    impl TestSyscallStubs {
+        pub fn new() -> Self {
+            TestSyscallStubs {
+                return_data: Arc::new(Mutex::new(None)),
+                invoked: Arc::new(Mutex::new(vec![])),
+                logged_data: Arc::new(Mutex::new(vec![])),
+            }
+        }
+    }
+
+    impl solana_program::program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            let seeds = signers_seeds
+                .iter()
+                .map(|seeds| seeds.iter().map(|seed| seed.to_vec()).collect())
+                .collect();
+            self.invoked
+                .lock()
+                .unwrap()
+                .push((instruction.clone(), seeds));
+
+            // Apply the owner change of system create_account (0) and assign
+            // (1), whose new owner is the trailing 32 bytes of the data and
+            // whose target is the last account
+            if system_program::check_id(&instruction.program_id)
+                && matches!(instruction.data.get(..4), Some([0, 0, 0, 0] | [1, 0, 0, 0]))
+            {
+                let owner =
+                    Pubkey::try_from(&instruction.data[instruction.data.len() - 32..]).unwrap();
+                let target = instruction.accounts.last().unwrap().pubkey;
+                if let Some(account) = account_infos.iter().find(|info| *info.key == target) {
+                    account.assign(&owner);
+                }
+            }
+            Ok(())
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut Rent) = Rent::default();
+            }
+            0
+        }
+
+        fn sol_log_data(&self, fields: &[&[u8]]) {
+            self.logged_data
+                .lock()
+                .unwrap()
+                .push(fields.iter().map(|field| field.to_vec()).collect());
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            *self.return_data.lock().unwrap() = Some(data.to_vec());
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            self.return_data
+                .lock()
+                .unwrap()
+                .clone()
+                .map(|data| (Pubkey::default(), data))
+        }
+    }
+
+    pub struct Test;
+
+    impl Test {
+        pub fn get_clock() -> FixedTime {
+            FixedTime(1000)
+        }
+
+        pub fn time_warp(seconds_to_advance: i64) -> FixedTime {
+            let base_timestamp = 1000;
+            FixedTime(base_timestamp + seconds_to_advance)
+        }
+
+        pub fn lock_stubs() -> MutexGuard<'static, ()> {
+            STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub const ONE_HOUR: i64 = 3600;
+        pub const ONE_DAY: i64 = 86400;
+        #[allow(dead_code)]
+        pub const ONE_WEEK: i64 = 86400 * 7;
+        pub const ONE_MONTH: i64 = 86400 * 30;
+    }
+
+    /// Backing storage for a test `AccountInfo`.
+    struct TestAccount {
+        key: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        owner: Pubkey,
+    }
+
+    impl TestAccount {
+        /// A zeroed stream account owned by `program_id`.
+        fn stream(program_id: &Pubkey) -> Self {
+            TestAccount {
+                key: Pubkey::new_unique(),
+                lamports: 0,
+                data: vec![0; StreamConfig::LEN],
+                owner: *program_id,
+            }
+        }
+
+        /// A stream account owned by `program_id` already holding `stream`.
+        fn holding(program_id: &Pubkey, stream: &StreamConfig) -> Self {
+            let mut account = Self::stream(program_id);
+            stream.serialize(&mut &mut account.data[..]).unwrap();
+            account
+        }
+
+        /// A wallet owned by the system program.
+        fn wallet() -> Self {
+            TestAccount {
+                key: Pubkey::new_unique(),
+                lamports: 0,
+                data: vec![],
+                owner: system_program::id(),
+            }
+        }
+
+        fn info(&mut self, is_signer: bool, is_writable: bool) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                is_signer,
+                is_writable,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                Epoch::default(),
+            )
+        }
+    }
+
+    /// The stream, sender and receiver accounts, in the order Initialize and
+    /// Terminate take them.
+    struct StreamAccounts {
+        stream: TestAccount,
+        sender: TestAccount,
+        receiver: TestAccount,
+    }
+
+    impl StreamAccounts {
+        fn new(program_id: &Pubkey) -> Self {
+            StreamAccounts {
+                stream: TestAccount::stream(program_id),
+                sender: TestAccount::wallet(),
+                receiver: TestAccount::wallet(),
+            }
+        }
+
+        /// Infos with the keypair stream account and the sender signing.
+        fn infos(&mut self) -> Vec<AccountInfo<'_>> {
+            vec![
+                self.stream.info(true, true),
+                self.sender.info(true, true),
+                self.receiver.info(false, false),
+            ]
+        }
+    }
+
+    /// Fields of `StreamInstruction::Initialize`. The default is a linear
+    /// keypair stream of 1 per second funded with 1000 that either party may
+    /// cancel, so tests only spell out what they exercise.
+    struct InitArgs {
+        flow_rate: i64,
+        flow_period: FlowPeriod,
+        curve: StreamCurve,
+        initial_balance: u64,
+        account_kind: StreamAccountKind,
+        cancel_policy: CancelPolicy,
+        reserved_balance: u64,
+        snap_start_to_midnight: bool,
+        emit_events: bool,
+        billing_period: i64,
+    }
+
+    impl Default for InitArgs {
+        fn default() -> Self {
+            InitArgs {
+                flow_rate: 1,
+                flow_period: FlowPeriod::Second,
+                curve: StreamCurve::Linear,
+                initial_balance: 1000,
+                account_kind: StreamAccountKind::Keypair,
+                cancel_policy: CancelPolicy::Either,
+                reserved_balance: 0,
+                snap_start_to_midnight: false,
+                emit_events: true,
+                billing_period: 0,
+            }
+        }
+    }
+
+    impl InitArgs {
+        fn instruction(self) -> StreamInstruction {
+            let InitArgs {
+                flow_rate,
+                flow_period,
+                curve,
+                initial_balance,
+                account_kind,
+                cancel_policy,
+                reserved_balance,
+                snap_start_to_midnight,
+                emit_events,
+                billing_period,
+            } = self;
+            StreamInstruction::Initialize {
+                flow_rate,
+                flow_period,
+                curve,
+                initial_balance,
+                account_kind,
+                cancel_policy,
+                reserved_balance,
+                snap_start_to_midnight,
+                emit_events,
+                billing_period,
+            }
+        }
+    }
+
+    /// Serializes `instruction` and runs it at the time reported by `time`.
+    fn process(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        instruction: StreamInstruction,
+        time: &dyn TimeSource,
+    ) -> ProgramResult {
+        let data = borsh::to_vec(&instruction).unwrap();
+        process_instruction_with_time(program_id, accounts, &data, time)
+    }
+
+    #[test]
+    fn test_initialization() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(
+            stream,
+            StreamConfig {
+                sender: *accounts[1].key,
+                receiver: *accounts[2].key,
+                flow_rate: 100,
+                flow_period: FlowPeriod::Second,
+                static_balance: 1000,
+                start_time: 1000, // Should match our mocked timestamp
+                terminated: false,
+                acknowledged: false,
+                acknowledged_at: 0,
+                cancel_policy: CancelPolicy::Either,
+                reserved_balance: 0,
+                checkpoint_ring: Default::default(),
+                checkpoints_written: 0,
+                account_kind: StreamAccountKind::Keypair,
+                bump: 0,
+                throttle_bps: FULL_RATE_BPS,
+                emit_events: true,
+                billing_period: 0,
+                created_at: 1000,
+                settled_balance: 0,
+                curve: StreamCurve::Linear,
+            }
+        );
+    }
+
+    #[test]
+    fn test_initialization_rejects_read_only_stream_account() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+        accounts[0].is_writable = false;
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &Test::get_clock()
+            ),
+            Err(StreamError::AccountNotWritable.into())
+        );
+        assert!(accounts[0].data.borrow().iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_initialization_keypair_requires_signature() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+        accounts[0].is_signer = false;
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &Test::get_clock()
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        accounts[0].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_initialization_rejects_live_stream() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            cancel_policy: CancelPolicy::None,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1000)),
+            Ok(())
+        );
+
+        // Re-initializing must not wipe a stream that cannot be terminated
+        let init = InitArgs {
+            flow_rate: 0,
+            initial_balance: 0,
+            cancel_policy: CancelPolicy::Either,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1400)),
+            Err(StreamError::AlreadyInitialized.into())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.cancel_policy, CancelPolicy::None);
+        assert_eq!(stream.static_balance, 1000);
+        assert_eq!(stream.start_time, 1000);
+    }
+
+    #[test]
+    fn test_initialization_snaps_start_to_midnight() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 100,
+            snap_start_to_midnight: true,
+            ..Default::default()
+        };
+        // Mid-day on 2024-01-01
+        let midnight = 1_704_067_200;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &FixedTime(midnight + 43200)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.start_time, midnight + 86400);
+        // Creation is recorded at the current clock, not the scheduled start
+        assert_eq!(stream.created_at, midnight + 43200);
+        // Nothing accrues before the snapped start
+        assert_eq!(stream.available_balance(midnight + 86399), Ok(0));
+    }
+
+    #[test]
+    fn test_initialization_logs_discovery_event() {
+        let _stubs = Test::lock_stubs();
+        let stubs = TestSyscallStubs::new();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(stubs.clone()));
+
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        let mut expected = b"stream_created".to_vec();
+        expected.extend_from_slice(accounts[1].key.as_ref());
+        expected.extend_from_slice(accounts[2].key.as_ref());
+        expected.extend_from_slice(accounts[0].key.as_ref());
+        expected.extend_from_slice(&1000i64.to_le_bytes());
+        let logged_data = stubs.logged_data.lock().unwrap();
+        assert_eq!(logged_data.len(), 1);
+        assert_eq!(logged_data[0].concat(), expected);
+        assert_eq!(logged_data[0].len(), 5);
+    }
+
+    #[test]
+    fn test_initialization_without_events() {
+        let _stubs = Test::lock_stubs();
+        let stubs = TestSyscallStubs::new();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(stubs.clone()));
+
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            emit_events: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        assert!(stubs.logged_data.lock().unwrap().is_empty());
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(!stream.emit_events);
+    }
+
+    #[test]
+    fn test_initialization_reports_unavailable_clock() {
+        let _stubs = Test::lock_stubs();
+        // These stubs leave the clock sysvar unsupported
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init_data = borsh::to_vec(&InitArgs::default().instruction()).unwrap();
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &init_data),
+            Err(StreamError::ClockUnavailable.into())
+        );
+    }
+
+    #[test]
+    fn test_initialization_program_address() {
+        let _stubs = Test::lock_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let sender_key = backing.sender.key;
+        let receiver_key = backing.receiver.key;
+        let (stream_key, bump) =
+            StreamConfig::find_address(&program_id, &sender_key, &receiver_key);
+
+        // The address does not exist yet
+        backing.stream.key = stream_key;
+        backing.stream.owner = system_program::id();
+        backing.sender.lamports = 10_000_000;
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::wallet()
+        };
+
+        let mut accounts = backing.infos();
+        // The program address never signs
+        accounts[0].is_signer = false;
+        let mut system_account = system.info(false, false);
+        system_account.executable = true;
+        accounts.push(system_account);
+
+        let stubs = TestSyscallStubs::new();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(stubs.clone()));
+
+        let init = InitArgs {
+            account_kind: StreamAccountKind::ProgramAddress,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        // The account is created by the program, paid for by the sender
+        let invoked = stubs.invoked.lock().unwrap();
+        assert_eq!(invoked.len(), 1);
+        let (instruction, seeds) = &invoked[0];
+        assert_eq!(
+            *instruction,
+            system_instruction::create_account(
+                &sender_key,
+                &stream_key,
+                Rent::default().minimum_balance(StreamConfig::LEN),
+                StreamConfig::LEN as u64,
+                &program_id,
+            )
+        );
+        assert_eq!(
+            *seeds,
+            vec![vec![
+                STREAM_SEED.to_vec(),
+                sender_key.to_bytes().to_vec(),
+                receiver_key.to_bytes().to_vec(),
+                vec![bump],
+            ]]
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.sender, sender_key);
+        assert_eq!(stream.receiver, receiver_key);
+        assert_eq!(stream.account_kind, StreamAccountKind::ProgramAddress);
+        assert_eq!(stream.bump, bump);
+        assert_eq!(stream.verify_address(&program_id, &stream_key), Ok(()));
+        assert_eq!(*accounts[0].owner, program_id);
+    }
+
+    #[test]
+    fn test_initialization_prefunded_program_address() {
+        let _stubs = Test::lock_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let sender_key = backing.sender.key;
+        let (stream_key, bump) =
+            StreamConfig::find_address(&program_id, &sender_key, &backing.receiver.key);
+
+        // Someone sent a lamport to the address before the stream was created
+        backing.stream.key = stream_key;
+        backing.stream.owner = system_program::id();
+        backing.stream.lamports = 1;
+        backing.sender.lamports = 10_000_000;
+        let mut system = TestAccount {
+            key: system_program::id(),
+            ..TestAccount::wallet()
+        };
+
+        let mut accounts = backing.infos();
+        accounts[0].is_signer = false;
+        let mut system_account = system.info(false, false);
+        system_account.executable = true;
+        accounts.push(system_account);
+
+        let stubs = TestSyscallStubs::new();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(stubs.clone()));
+
+        let init = InitArgs {
+            account_kind: StreamAccountKind::ProgramAddress,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        // The sender tops up the rent, then the program takes the account over
+        let invoked = stubs.invoked.lock().unwrap();
+        let instructions: Vec<_> = invoked.iter().map(|(ix, _)| ix.clone()).collect();
+        assert_eq!(
+            instructions,
+            vec![
+                system_instruction::transfer(
+                    &sender_key,
+                    &stream_key,
+                    Rent::default().minimum_balance(StreamConfig::LEN) - 1,
+                ),
+                system_instruction::allocate(&stream_key, StreamConfig::LEN as u64),
+                system_instruction::assign(&stream_key, &program_id),
+            ]
+        );
+        assert_eq!(invoked[2].1[0].last(), Some(&vec![bump]));
+
+        assert_eq!(*accounts[0].owner, program_id);
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.bump, bump);
+    }
+
+    #[test]
+    fn test_initialization_rejects_wrong_program_address() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            account_kind: StreamAccountKind::ProgramAddress,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Err(StreamError::InvalidStreamAddress.into())
+        );
+    }
+
+    #[test]
+    fn test_rejects_program_address_stream_at_other_address() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let (stream_key, bump) =
+            StreamConfig::find_address(&program_id, &backing.sender.key, &backing.receiver.key);
+        let stream = StreamConfig {
+            account_kind: StreamAccountKind::ProgramAddress,
+            bump,
+            ..StreamConfig::initialize(
+                backing.sender.key,
+                backing.receiver.key,
+                10,
+                FlowPeriod::Second,
+                StreamCurve::Linear,
+                1000,
+                1000,
+            )
+        };
+        backing.stream = TestAccount::holding(&program_id, &stream);
+
+        let accounts = backing.infos();
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1010)
+            ),
+            Err(StreamError::InvalidStreamAddress.into())
+        );
+        drop(accounts);
+
+        // The same data at the derived address is accepted
+        backing.stream.key = stream_key;
+        let accounts = backing.infos();
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1010)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_termination() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        accounts[1].is_signer = false;
+        accounts[2].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &Test::time_warp(Test::ONE_DAY)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(
+            stream,
+            StreamConfig {
+                sender: *accounts[1].key,
+                receiver: *accounts[2].key,
+                flow_rate: 100,
+                flow_period: FlowPeriod::Second,
+                static_balance: 0,
+                start_time: 1000,
+                terminated: true,
+                acknowledged: false,
+                acknowledged_at: 0,
+                cancel_policy: CancelPolicy::Either,
+                reserved_balance: 0,
+                checkpoint_ring: Default::default(),
+                checkpoints_written: 0,
+                account_kind: StreamAccountKind::Keypair,
+                bump: 0,
+                throttle_bps: FULL_RATE_BPS,
+                emit_events: true,
+                billing_period: 0,
+                created_at: 1000,
+                settled_balance: 1000,
+                curve: StreamCurve::Linear,
+            }
+        );
+    }
+
+    #[test]
+    fn test_preview_withdraw() {
+        let _stubs = Test::lock_stubs();
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 100,
+            initial_balance: 1_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+        let data_before = accounts[0].data.borrow().to_vec();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts[..1],
+                StreamInstruction::PreviewWithdraw,
+                &Test::time_warp(Test::ONE_HOUR)
+            ),
+            Ok(())
+        );
+
+        let (_, return_data) = get_return_data().unwrap();
+        let payable = u64::from_le_bytes(return_data.try_into().unwrap());
+        assert_eq!(payable, 100 * Test::ONE_HOUR as u64);
+
+        assert_eq!(accounts[0].data.borrow().to_vec(), data_before);
+    }
+
+    #[test]
+    fn test_termination_settles_partial_accrual() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 600);
+    }
+
+    #[test]
+    fn test_termination_after_accrual_overflow() {
+        let _stubs = Test::lock_stubs();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 1_000_000_000_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1000)),
+            Ok(())
+        );
+
+        // A day's accrual no longer fits in a u64, which pays out everything
+        let day_later = Test::time_warp(Test::ONE_DAY);
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts[..1],
+                StreamInstruction::PreviewWithdraw,
+                &day_later
+            ),
+            Ok(())
+        );
+        let (_, return_data) = get_return_data().unwrap();
+        assert_eq!(return_data, 1000u64.to_le_bytes());
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &day_later
+            ),
+            Ok(())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(stream.terminated);
+        assert_eq!(stream.static_balance, 0);
+    }
+
+    #[test]
+    fn test_termination_mid_billing_period() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            billing_period: 300,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1000)),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        // Only the first 300 second period was earned; the current one stays
+        // with the sender
+        assert_eq!(stream.static_balance, 700);
+    }
+
+    #[test]
+    fn test_set_reserve() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        let accounts = &accounts[..2];
+        let set_reserve = |amount| {
+            process(
+                &program_id,
+                accounts,
+                StreamInstruction::SetReserve { amount },
+                &FixedTime(1400),
+            )
+        };
+        let reserve = || {
+            StreamConfig::unpack(&accounts[0].data.borrow())
+                .unwrap()
+                .reserved_balance
+        };
+
+        // 400 is payable, so at most 600 can be reserved
+        assert_eq!(set_reserve(1001), Err(StreamError::InvalidReserve.into()));
+        assert_eq!(
+            set_reserve(601),
+            Err(StreamError::InsufficientUnstreamedBalance.into())
+        );
+        assert_eq!(set_reserve(600), Ok(()));
+        assert_eq!(reserve(), 600);
+
+        assert_eq!(set_reserve(200), Ok(()));
+        assert_eq!(reserve(), 200);
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.available_balance(1900), Ok(800));
+    }
+
+    #[test]
+    fn test_decline_stream() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        backing.stream.lamports = 500;
+        let mut accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        // Only the receiver can decline
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::DeclineStream,
+                &FixedTime(1400)
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        accounts[1].is_signer = false;
+        accounts[2].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::DeclineStream,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        // Nothing was settled to the receiver; the sender got everything back
+        assert_eq!(accounts[0].lamports(), 0);
+        assert!(accounts[0].data.borrow().iter().all(|byte| *byte == 0));
+        assert_eq!(accounts[1].lamports(), 500);
+        assert_eq!(accounts[2].lamports(), 0);
+
+        // The zeroed account no longer reads as a stream
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts[..1],
+                StreamInstruction::GetState,
+                &FixedTime(1500)
+            ),
+            Err(StreamError::NotInitialized.into())
+        );
+
+        // Once terminated the settlement record stays
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+        accounts[2].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::DeclineStream,
+                &FixedTime(1500)
+            ),
+            Err(StreamError::StreamTerminated.into())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(stream.terminated);
+        assert_eq!(stream.static_balance, 600);
+    }
+
+    #[test]
+    fn test_reinitialize_declined_stream() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+        accounts[2].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::DeclineStream,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        // Declining zeroes the account, so it can hold a fresh stream again
+        let init = InitArgs {
+            flow_rate: 5,
+            initial_balance: 2000,
+            ..Default::default()
+        }
+        .instruction();
+        assert_eq!(
+            process(&program_id, &accounts, init.clone(), &FixedTime(1500)),
+            Ok(())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.flow_rate, 5);
+        assert_eq!(stream.static_balance, 2000);
+        assert_eq!(stream.start_time, 1500);
+        assert_eq!(stream.created_at, 1500);
+
+        // While it is live it cannot be initialized over
+        assert_eq!(
+            process(&program_id, &accounts, init, &FixedTime(1600)),
+            Err(StreamError::AlreadyInitialized.into())
+        );
+    }
+
+    #[test]
+    fn test_clawback_leaves_owed_funds() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        let accounts = &accounts[..2];
+        let clawback = |amount| {
+            process(
+                &program_id,
+                accounts,
+                StreamInstruction::Clawback { amount },
+                &FixedTime(1400),
+            )
+        };
+
+        // 400 is owed to the receiver, so at most 600 can be taken back
+        assert_eq!(
+            clawback(601),
+            Err(StreamError::InsufficientUnstreamedBalance.into())
+        );
+        assert_eq!(clawback(500), Ok(()));
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 500);
+        assert!(!stream.terminated);
+        assert_eq!(stream.available_balance(1400), Ok(400));
+        // The stream now runs dry 100 seconds later
+        assert_eq!(stream.depletion_time(1400), Some(1500));
+    }
+
+    #[test]
+    fn test_throttle_and_resume() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        let accounts = &accounts[..2];
+        let throttle = |factor_bps, now| {
+            process(
+                &program_id,
+                accounts,
+                StreamInstruction::Throttle { factor_bps },
+                &FixedTime(now),
+            )
+        };
+
+        assert_eq!(
+            throttle(FULL_RATE_BPS + 1, 1400),
+            Err(StreamError::InvalidThrottle.into())
+        );
+
+        // 400 settled at the full rate, then half speed
+        assert_eq!(throttle(5000, 1400), Ok(()));
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 600);
+        assert_eq!(stream.settled_balance, 400);
+        assert_eq!(stream.available_balance(1600), Ok(100));
+        // Progress counts what was settled rather than restarting from zero
+        assert_eq!(stream.percent_streamed(1400), Some(40));
+        assert_eq!(stream.percent_streamed(1600), Some(50));
+
+        // 100 settled at half speed, then the full rate again
+        assert_eq!(throttle(FULL_RATE_BPS, 1600), Ok(()));
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 500);
+        assert_eq!(stream.settled_balance, 500);
+        assert_eq!(stream.throttle_bps, FULL_RATE_BPS);
+        assert_eq!(stream.percent_streamed(1700), Some(60));
+        assert_eq!(stream.available_balance(1700), Ok(100));
+    }
+
+    #[test]
+    fn test_restart_keeps_scheduled_start() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut closed = TestAccount::stream(&program_id);
+        let accounts = backing.infos();
+        let closed_account = closed.info(true, true);
+
+        // Both streams start at the midnight after they are initialized
+        let midnight = 1_704_067_200;
+        for stream_account in [&accounts[0], &closed_account] {
+            let init = InitArgs {
+                snap_start_to_midnight: true,
+                ..Default::default()
+            };
+            let init_accounts = [
+                stream_account.clone(),
+                accounts[1].clone(),
+                accounts[2].clone(),
+            ];
+            assert_eq!(
+                process(
+                    &program_id,
+                    &init_accounts,
+                    init.instruction(),
+                    &FixedTime(midnight + 60)
+                ),
+                Ok(())
+            );
+        }
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts[..2],
+                StreamInstruction::Throttle {
+                    factor_bps: FULL_RATE_BPS
+                },
+                &FixedTime(midnight + 3600)
+            ),
+            Ok(())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.start_time, midnight + 86400);
+        assert_eq!(stream.available_balance(midnight + 86400), Ok(0));
+
+        let accounts = [accounts[0].clone(), closed_account, accounts[1].clone()];
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::MergeStreams,
+                &FixedTime(midnight + 7200)
+            ),
+            Ok(())
+        );
+        let kept = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(kept.start_time, midnight + 86400);
+        assert_eq!(kept.static_balance, 2000);
+        assert_eq!(kept.available_balance(midnight + 86400), Ok(0));
+    }
+
+    #[test]
+    fn test_over_allocated_stream_account() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        // Room left for future fields
+        backing.stream.data = vec![0; StreamConfig::LEN + 64];
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 600);
+        assert!(stream.terminated);
+        assert!(accounts[0].data.borrow()[StreamConfig::LEN..]
+            .iter()
+            .all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_termination_rejects_stranger_receiver() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        let mut stranger = TestAccount::wallet();
+        let accounts = vec![
+            accounts[0].clone(),
+            accounts[1].clone(),
+            stranger.info(false, false),
+        ];
+
+        // The sender's signature does not excuse the wrong receiver
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1400)
+            ),
+            Err(StreamError::InvalidAccount.into())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(!stream.terminated);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &FixedTime(1000)
+            ),
+            Ok(())
+        );
+
+        // The receiver has not signed
+        let stream_only = vec![accounts[0].clone(), accounts[2].clone()];
+        assert_eq!(
+            process(
+                &program_id,
+                &stream_only,
+                StreamInstruction::Checkpoint,
+                &FixedTime(1400)
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts[..2],
+                StreamInstruction::Checkpoint,
+                &FixedTime(1400)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(
+            stream.checkpoints(),
+            vec![Checkpoint {
+                timestamp: 1400,
+                available: 400,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_termination_leaves_reserve() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let accounts = backing.infos();
+
+        let init = InitArgs {
+            reserved_balance: 1001,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1000)),
+            Err(StreamError::InvalidReserve.into())
+        );
+
+        let init = InitArgs {
+            reserved_balance: 500,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(&program_id, &accounts, init.instruction(), &FixedTime(1000)),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &FixedTime(1600)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        // Accrual stops at the reserve, which stays for the sender
+        assert_eq!(stream.static_balance, 500);
+        assert_eq!(stream.reserved_balance, 500);
+    }
+
+    #[test]
+    fn test_termination_respects_cancel_policy() {
+        let not_permitted: ProgramResult = Err(StreamError::TerminationNotPermitted.into());
+        let unsigned: ProgramResult = Err(ProgramError::MissingRequiredSignature);
+        // (policy, result when the sender signs, result when the receiver signs)
+        let cases = [
+            (CancelPolicy::SenderOnly, Ok(()), unsigned.clone()),
+            (CancelPolicy::ReceiverOnly, unsigned.clone(), Ok(())),
+            (CancelPolicy::Either, Ok(()), Ok(())),
+            (CancelPolicy::None, not_permitted.clone(), not_permitted),
+        ];
+
+        for (cancel_policy, by_sender, by_receiver) in cases {
+            for (sender_signs, expected) in [(true, by_sender), (false, by_receiver)] {
+                let program_id = Pubkey::new_unique();
+                let mut backing = StreamAccounts::new(&program_id);
+                let mut accounts = backing.infos();
+
+                let init = InitArgs {
+                    cancel_policy,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    process(
+                        &program_id,
+                        &accounts,
+                        init.instruction(),
+                        &Test::get_clock()
+                    ),
+                    Ok(())
+                );
+
+                accounts[1].is_signer = sender_signs;
+                accounts[2].is_signer = !sender_signs;
+                assert_eq!(
+                    process(
+                        &program_id,
+                        &accounts,
+                        StreamInstruction::Terminate,
+                        &Test::time_warp(Test::ONE_HOUR)
+                    ),
+                    expected,
+                    "{:?} terminated by the {}",
+                    cancel_policy,
+                    if sender_signs { "sender" } else { "receiver" }
+                );
+
+                let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+                assert_eq!(stream.cancel_policy, cancel_policy);
+                assert_eq!(stream.terminated, expected.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_streams() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        backing.stream.lamports = 100;
+        let mut closed = TestAccount::stream(&program_id);
+        closed.lamports = 100;
+        let accounts = backing.infos();
+        let closed_account = closed.info(true, true);
+
+        for (stream_account, flow_rate, initial_balance) in
+            [(&accounts[0], 1, 1000), (&closed_account, 2, 500)]
+        {
+            let init = InitArgs {
+                flow_rate,
+                initial_balance,
+                ..Default::default()
+            };
+            let init_accounts = [
+                stream_account.clone(),
+                accounts[1].clone(),
+                accounts[2].clone(),
+            ];
+            assert_eq!(
+                process(
+                    &program_id,
+                    &init_accounts,
+                    init.instruction(),
+                    &FixedTime(1000)
+                ),
+                Ok(())
+            );
+        }
+
+        let into_itself = [
+            accounts[0].clone(),
+            accounts[0].clone(),
+            accounts[1].clone(),
+        ];
+        assert_eq!(
+            process(
+                &program_id,
+                &into_itself,
+                StreamInstruction::MergeStreams,
+                &FixedTime(1100)
+            ),
+            Err(StreamError::StreamsNotMergeable.into())
+        );
+
+        let accounts = [accounts[0].clone(), closed_account, accounts[1].clone()];
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::MergeStreams,
+                &FixedTime(1100)
+            ),
+            Ok(())
+        );
+
+        // 100 and 200 were settled, the rest restarts at the combined rate
+        let kept = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(kept.static_balance, 900 + 300);
+        assert_eq!(kept.settled_balance, 100 + 200);
+        assert_eq!(kept.flow_rate, 3);
+        assert_eq!(kept.start_time, 1100);
+        assert_eq!(kept.available_balance(1200), Ok(300));
+
+        // The second stream is closed and its rent refunded
+        assert_eq!(accounts[1].lamports(), 0);
+        assert!(accounts[1].data.borrow().iter().all(|byte| *byte == 0));
+        assert_eq!(accounts[2].lamports(), 100);
+    }
+
+    #[test]
+    fn test_merge_streams_keeps_billing_phase() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut closed = TestAccount::stream(&program_id);
+        let mut offset = TestAccount::stream(&program_id);
+        let accounts = backing.infos();
+        let closed_account = closed.info(true, true);
+        let offset_account = offset.info(true, true);
+
+        for (stream_account, start_time) in [
+            (&accounts[0], 1000),
+            (&closed_account, 1000),
+            (&offset_account, 1060),
+        ] {
+            let init = InitArgs {
+                initial_balance: 5_000_000,
+                cancel_policy: CancelPolicy::None,
+                billing_period: Test::ONE_MONTH,
+                ..Default::default()
+            };
+            let init_accounts = [
+                stream_account.clone(),
+                accounts[1].clone(),
+                accounts[2].clone(),
+            ];
+            assert_eq!(
+                process(
+                    &program_id,
+                    &init_accounts,
+                    init.instruction(),
+                    &FixedTime(start_time)
+                ),
+                Ok(())
+            );
+        }
+
+        let merge_at = Test::time_warp(Test::ONE_MONTH - 1);
+
+        // A stream a minute into its period would lose or gain that minute
+        let misaligned = [accounts[0].clone(), offset_account, accounts[1].clone()];
+        assert_eq!(
+            process(
+                &program_id,
+                &misaligned,
+                StreamInstruction::MergeStreams,
+                &merge_at
+            ),
+            Err(StreamError::StreamsNotMergeable.into())
+        );
+
+        let accounts = [accounts[0].clone(), closed_account, accounts[1].clone()];
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::MergeStreams,
+                &merge_at
+            ),
+            Ok(())
+        );
+
+        // Nothing was credited yet, and the period that was almost over still
+        // completes a second later at the combined rate
+        let kept = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(kept.static_balance, 10_000_000);
+        assert_eq!(kept.start_time, 1000);
+        assert_eq!(
+            kept.available_balance(1000 + Test::ONE_MONTH),
+            Ok(2 * Test::ONE_MONTH as u64)
+        );
+    }
+
+    #[test]
+    fn test_can_withdraw() {
+        let _stubs = Test::lock_stubs();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+
+        let program_id = Pubkey::new_unique();
+        let stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            1000,
+        );
+        let mut stream_account = TestAccount::holding(&program_id, &stream);
+        let accounts = [stream_account.info(false, false)];
+
+        // 300 is payable after 30 seconds
+        for (amount, expected) in [(0, 1), (300, 1), (301, 0)] {
+            assert_eq!(
+                process(
+                    &program_id,
+                    &accounts,
+                    StreamInstruction::CanWithdraw { amount },
+                    &Test::time_warp(30)
+                ),
+                Ok(())
+            );
+            let (_, return_data) = get_return_data().unwrap();
+            assert_eq!(return_data, vec![expected], "amount {}", amount);
+        }
+    }
+
+    #[test]
+    fn test_get_state_summary() {
+        let _stubs = Test::lock_stubs();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+
+        let program_id = Pubkey::new_unique();
+        let receiver_key = Pubkey::new_unique();
+        let stream = StreamConfig {
+            reserved_balance: 100,
+            ..StreamConfig::initialize(
+                Pubkey::new_unique(),
+                receiver_key,
+                10,
+                FlowPeriod::Minute,
+                StreamCurve::Linear,
+                1000,
+                1000,
+            )
+        };
+        let mut stream_account = TestAccount::holding(&program_id, &stream);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &[stream_account.info(false, false)],
+                StreamInstruction::GetState,
+                &Test::time_warp(Test::ONE_HOUR)
+            ),
+            Ok(())
+        );
+
+        let (_, return_data) = get_return_data().unwrap();
+        assert_eq!(
+            StreamSummary::try_from_slice(&return_data).unwrap(),
+            StreamSummary {
+                receiver: receiver_key,
+                payable: 600,
+                remaining: 400,
+                flow_rate: 10,
+                flow_period: FlowPeriod::Minute,
+                status: StreamStatus::Active,
+                current_flow_rate: 10,
+                depletion_time: Some(1000 + 90 * 60),
+                percent_streamed: Some(66),
+            }
+        );
+    }
+
+    #[test]
+    fn test_projection() {
+        let _stubs = Test::lock_stubs();
+        solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs::new()));
+
+        let program_id = Pubkey::new_unique();
+        let funded = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            1000,
+        );
+        let paused = StreamConfig {
+            throttle_bps: 0,
+            ..funded.clone()
+        };
+
+        for (stream, expected) in [(funded, 1100), (paused, i64::MAX)] {
+            let mut stream_account = TestAccount::holding(&program_id, &stream);
+            assert_eq!(
+                process(
+                    &program_id,
+                    &[stream_account.info(false, false)],
+                    StreamInstruction::Projection,
+                    &Test::time_warp(30)
+                ),
+                Ok(())
+            );
+            let (_, return_data) = get_return_data().unwrap();
+            assert_eq!(return_data, expected.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_acknowledge_settlement() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut accounts = backing.infos();
+        accounts[2].is_signer = true;
+        let ack_accounts = vec![accounts[0].clone(), accounts[2].clone()];
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                InitArgs::default().instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        // Nothing to acknowledge while the stream is running
+        assert_eq!(
+            process(
+                &program_id,
+                &ack_accounts,
+                StreamInstruction::AcknowledgeSettlement,
+                &Test::time_warp(100)
+            ),
+            Err(StreamError::StreamNotTerminated.into())
+        );
+
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &Test::time_warp(400)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &Test::time_warp(500)
+            ),
+            Err(StreamError::StreamTerminated.into())
+        );
+
+        assert_eq!(
+            process(
+                &program_id,
+                &ack_accounts,
+                StreamInstruction::AcknowledgeSettlement,
+                &Test::time_warp(Test::ONE_HOUR)
+            ),
+            Ok(())
+        );
+
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(stream.terminated);
+        assert!(stream.acknowledged);
+        assert_eq!(stream.acknowledged_at, 1000 + Test::ONE_HOUR);
+        assert_eq!(stream.static_balance, 600);
+
+        assert_eq!(
+            process(
+                &program_id,
+                &ack_accounts,
+                StreamInstruction::AcknowledgeSettlement,
+                &Test::time_warp(Test::ONE_DAY)
+            ),
+            Err(StreamError::AlreadyAcknowledged.into())
+        );
+    }
+
+    #[test]
+    fn test_termination_rejects_misowned_receiver() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        // The receiver wallet is owned by the stream program instead of the system program
+        backing.receiver.owner = program_id;
+        let mut accounts = backing.infos();
+
+        let init = InitArgs {
+            flow_rate: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        // The receiver cannot terminate from a wallet the system program doesn't own
+        accounts[1].is_signer = false;
+        accounts[2].is_signer = true;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &Test::get_clock()
+            ),
+            Err(StreamError::InvalidAccountOwner.into())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert_eq!(stream.static_balance, 1000);
+        assert!(!stream.terminated);
+
+        // But it does not stop the sender from terminating
+        accounts[1].is_signer = true;
+        accounts[2].is_signer = false;
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                StreamInstruction::Terminate,
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+        let stream = StreamConfig::unpack(&accounts[0].data.borrow()).unwrap();
+        assert!(stream.terminated);
+    }
+
+    #[test]
+    fn test_clone() {
+        let program_id = Pubkey::new_unique();
+        let mut backing = StreamAccounts::new(&program_id);
+        let mut stream = TestAccount::stream(&program_id);
+        let accounts = backing.infos();
+
+        // Initialize the template stream
+        let init = InitArgs {
+            flow_rate: 5,
+            flow_period: FlowPeriod::Hour,
+            curve: StreamCurve::ExponentialDecay {
+                half_life: Test::ONE_DAY,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            process(
+                &program_id,
+                &accounts,
+                init.instruction(),
+                &Test::get_clock()
+            ),
+            Ok(())
+        );
+
+        let clone_accounts = [
+            accounts[0].clone(),
+            stream.info(true, true),
+            accounts[1].clone(),
+        ];
+        let clone = StreamInstruction::Clone {
+            initial_balance: 5000,
+        };
+        let clock = Test::time_warp(Test::ONE_DAY);
+
+        // The new keypair account has to sign to be claimed
+        let mut unsigned = clone_accounts.clone();
+        unsigned[1].is_signer = false;
+        assert_eq!(
+            process(&program_id, &unsigned, clone.clone(), &clock),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        assert_eq!(
+            process(&program_id, &clone_accounts, clone.clone(), &clock),
+            Ok(())
+        );
+
+        let template = StreamConfig::unpack(&clone_accounts[0].data.borrow()).unwrap();
+        let stream = StreamConfig::unpack(&clone_accounts[1].data.borrow()).unwrap();
+        assert_eq!(
+            stream,
+            StreamConfig {
+                static_balance: 5000,
+                start_time: 1000 + Test::ONE_DAY,
+                created_at: 1000 + Test::ONE_DAY,
+                ..template
+            }
+        );
+
+        // Cloning into an account that already holds a stream is rejected
+        assert_eq!(
+            process(&program_id, &clone_accounts, clone, &clock),
+            Err(StreamError::AlreadyInitialized.into())
+        );
+    }
+
+    #[test]
+    fn test_rejects_empty_instruction_data() {
+        let program_id = Pubkey::new_unique();
+
+        assert_eq!(
+            process_instruction(&program_id, &[], &[]),
+            Err(StreamError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_rejects_truncated_instruction_data() {
+        let program_id = Pubkey::new_unique();
+
+        let mut init_data = borsh::to_vec(&InitArgs::default().instruction()).unwrap();
+        init_data.truncate(init_data.len() - 1);
+
+        assert_eq!(
+            process_instruction(&program_id, &[], &init_data),
+            Err(StreamError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn test_rejects_trailing_instruction_data() {
+        let program_id = Pubkey::new_unique();
+
+        let mut term_data = borsh::to_vec(&StreamInstruction::Terminate).unwrap();
+        term_data.push(0);
+
+        assert_eq!(
+            process_instruction(&program_id, &[], &term_data),
+            Err(StreamError::InvalidInstruction.into())
+        );
     }
 }
 
@@ -1,29 +1,415 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::pubkey::Pubkey;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::fmt;
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+use crate::error::StreamError;
+use crate::math::{apply_accrual, checked_accrual};
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowPeriod {
+    Second,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl FlowPeriod {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            FlowPeriod::Second => 1,
+            FlowPeriod::Minute => 60,
+            FlowPeriod::Hour => 3600,
+            FlowPeriod::Day => 86400,
+        }
+    }
+
+    /// Amount accrued over `elapsed` seconds at `flow_rate` per period. Whole
+    /// periods and the remainder are accrued separately so long durations
+    /// don't overflow before the division.
+    pub fn accrual(&self, flow_rate: i64, elapsed: i64) -> Result<u64, StreamError> {
+        let period = self.seconds();
+        let whole_periods = checked_accrual(flow_rate, elapsed / period)?;
+        let partial_period = checked_accrual(flow_rate, elapsed % period)? / period as u64;
+        whole_periods
+            .checked_add(partial_period)
+            .ok_or(StreamError::AccrualOverflow)
+    }
+}
+
+/// Shape of the payout over time.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamCurve {
+    /// Pays `flow_rate` every `flow_period`.
+    Linear,
+    /// Starts at `flow_rate` and halves every `half_life` seconds. The rate is
+    /// held constant within each half-life so accrual stays in integer math.
+    /// The lifetime payout is capped at twice what the first half-life pays.
+    ExponentialDecay { half_life: i64 },
+    /// Releases `chunk` at the end of every `interval` seconds, ignoring
+    /// `flow_rate` and `flow_period`. Nothing is released between boundaries.
+    Drip { chunk: u64, interval: i64 },
+}
+
+impl StreamCurve {
+    /// Largest serialized size across variants
+    pub const MAX_LEN: usize = 1 + 8 + 8;
+
+    /// Checks the curve's parameters, and that its payout at `flow_rate` per
+    /// `flow_period` can always be accrued without overflowing.
+    pub fn validate(&self, flow_rate: i64, flow_period: FlowPeriod) -> Result<(), StreamError> {
+        match self {
+            StreamCurve::Linear => Ok(()),
+            StreamCurve::ExponentialDecay { half_life } if *half_life > 0 => {
+                // The lifetime payout is at most twice the first half-life's
+                flow_period
+                    .accrual(flow_rate, *half_life)
+                    .ok()
+                    .and_then(|per_half_life| per_half_life.checked_mul(2))
+                    .map(|_| ())
+                    .ok_or(StreamError::InvalidCurve)
+            }
+            StreamCurve::ExponentialDecay { .. } => Err(StreamError::InvalidCurve),
+            StreamCurve::Drip { interval, .. } if *interval > 0 => Ok(()),
+            StreamCurve::Drip { .. } => Err(StreamError::InvalidCurve),
+        }
+    }
+
+    /// Amount streamed after `elapsed` seconds at `flow_rate` per `flow_period`.
+    fn streamed(
+        &self,
+        flow_rate: i64,
+        flow_period: FlowPeriod,
+        elapsed: i64,
+    ) -> Result<u64, StreamError> {
+        match self {
+            StreamCurve::Linear => flow_period.accrual(flow_rate, elapsed),
+            StreamCurve::ExponentialDecay { half_life } => {
+                // A zero half-life is rejected by `validate`, but configs
+                // decoded off-chain may not have been validated
+                let elapsed = elapsed.max(0);
+                let halvings = elapsed
+                    .checked_div(*half_life)
+                    .ok_or(StreamError::InvalidCurve)?;
+                let remainder = elapsed
+                    .checked_rem(*half_life)
+                    .ok_or(StreamError::InvalidCurve)?;
+
+                // Every term has reached zero by 64 halvings
+                let per_half_life = flow_period.accrual(flow_rate, *half_life)?;
+                let mut streamed: u64 = 0;
+                for k in 0..halvings.min(64) {
+                    streamed = streamed
+                        .checked_add(per_half_life >> k)
+                        .ok_or(StreamError::AccrualOverflow)?;
+                }
+                if halvings < 64 {
+                    streamed = streamed
+                        .checked_add(flow_period.accrual(flow_rate, remainder)? >> halvings)
+                        .ok_or(StreamError::AccrualOverflow)?;
+                }
+                Ok(streamed)
+            }
+            StreamCurve::Drip { chunk, interval } => {
+                // A zero interval is rejected by `validate`, but configs
+                // decoded off-chain may not have been validated
+                let drips = elapsed
+                    .checked_div(*interval)
+                    .ok_or(StreamError::InvalidCurve)?;
+                let drips = u64::try_from(drips).unwrap_or(0);
+                chunk.checked_mul(drips).ok_or(StreamError::AccrualOverflow)
+            }
+        }
+    }
+}
+
+/// Seed prefix for stream program addresses, followed by the sender and receiver keys
+pub const STREAM_SEED: &[u8] = b"stream";
+
+/// First field of the `sol_log_data` event emitted whenever a stream is
+/// created, so clients can discover streams by scanning program logs. The
+/// event fields are `[STREAM_CREATED_EVENT, sender, receiver, stream,
+/// created_at]`, each key as its 32 raw bytes and `created_at` as a
+/// little-endian `i64`, and appear in the logs base64 encoded as
+/// `Program data: <field> <field> <field> <field> <field>`.
+pub const STREAM_CREATED_EVENT: &[u8] = b"stream_created";
+
+/// Number of checkpoints a stream keeps; older ones are overwritten.
+pub const CHECKPOINT_SLOTS: usize = 4;
+
+/// Available balance recorded by the Checkpoint instruction.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub timestamp: i64,
+    pub available: u64,
+}
+
+/// `throttle_bps` of an unthrottled stream
+pub const FULL_RATE_BPS: u16 = 10_000;
+
+/// Who may terminate a stream, fixed by the sender at Initialize.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CancelPolicy {
+    SenderOnly,
+    ReceiverOnly,
+    #[default]
+    Either,
+    /// The stream runs until its balance is exhausted
+    None,
+}
+
+impl CancelPolicy {
+    pub fn sender_may_cancel(&self) -> bool {
+        matches!(self, CancelPolicy::SenderOnly | CancelPolicy::Either)
+    }
+
+    pub fn receiver_may_cancel(&self) -> bool {
+        matches!(self, CancelPolicy::ReceiverOnly | CancelPolicy::Either)
+    }
+}
+
+/// How the stream account passed to Initialize was created.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamAccountKind {
+    /// A fresh keypair account created by the client beforehand. It must sign
+    /// Initialize, otherwise anyone could claim an account someone else allocated.
+    Keypair,
+    /// The program address derived from `[STREAM_SEED, sender, receiver]`. It
+    /// cannot sign; the derivation ties it to the sender instead. The program
+    /// creates the account, funded by the sender, if it does not exist yet,
+    /// including when lamports were already sent to the address.
+    ProgramAddress,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq, Clone)]
 pub struct StreamConfig {
     pub sender: Pubkey,
     pub receiver: Pubkey,
+    /// Amount streamed per `flow_period`
     pub flow_rate: i64,
+    pub flow_period: FlowPeriod,
     pub static_balance: u64,
     pub start_time: i64,
+    /// Set by Terminate; a terminated stream no longer accrues
+    pub terminated: bool,
+    /// Set when the receiver acknowledges the final payout of a terminated stream
+    pub acknowledged: bool,
+    pub acknowledged_at: i64,
+    pub cancel_policy: CancelPolicy,
+    /// Part of `static_balance` held back from the receiver, left for the sender
+    pub reserved_balance: u64,
+    /// Ring buffer of the last `CHECKPOINT_SLOTS` checkpoints, see `checkpoints`
+    pub checkpoint_ring: [Checkpoint; CHECKPOINT_SLOTS],
+    /// Checkpoints recorded over the stream's lifetime
+    pub checkpoints_written: u64,
+    pub account_kind: StreamAccountKind,
+    /// Bump of the program address, or 0 for a keypair account
+    pub bump: u8,
+    /// Share of the curve's payout that accrues, in basis points of `FULL_RATE_BPS`
+    pub throttle_bps: u16,
+    /// Whether `sol_log_data` events are emitted for this stream. Turning them
+    /// off saves compute, but log-scanning clients then cannot discover the
+    /// stream; the `msg!` logs are unaffected.
+    pub emit_events: bool,
+    /// When positive, accrual is only credited at the end of each completed
+    /// billing period of this many seconds, so terminating mid-period leaves
+    /// the whole current period with the sender. Zero accrues continuously.
+    pub billing_period: i64,
+    /// Clock at Initialize or Clone. Unlike `start_time` it is never
+    /// scheduled ahead or moved by a restart.
+    pub created_at: i64,
+    /// Amount settled to the receiver by Terminate, Throttle and MergeStreams,
+    /// which `static_balance` no longer includes
+    pub settled_balance: u64,
+    pub curve: StreamCurve,
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq, Clone)]
 pub enum StreamInstruction {
+    /// Starts a stream from the sender to the receiver.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` New, zeroed stream account, owned by this program.
+    ///    Signs when `account_kind` is `Keypair`.
+    /// 1. `[signer, writable]` Sender, pays for a program address account
+    /// 2. `[]` Receiver
+    /// 3. `[]` System program, only needed when a program address account
+    ///    has to be created
     Initialize {
         flow_rate: i64,
+        flow_period: FlowPeriod,
+        curve: StreamCurve,
         initial_balance: u64,
+        account_kind: StreamAccountKind,
+        cancel_policy: CancelPolicy,
+        /// Part of `initial_balance` that never streams
+        reserved_balance: u64,
+        /// Start at the next UTC midnight instead of now, for calendar streams
+        snap_start_to_midnight: bool,
+        /// See `StreamConfig::emit_events`
+        emit_events: bool,
+        /// See `StreamConfig::billing_period`
+        billing_period: i64,
     },
+    /// Settles the streamed amount and ends the stream.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[]` Sender wallet
+    /// 2. `[]` Receiver wallet
+    ///
+    /// A party allowed by the stream's `cancel_policy` must sign, from a
+    /// wallet owned by the system program.
     Terminate,
+    /// Returns the amount currently payable to the receiver via return data
+    /// without writing to the stream account.
+    PreviewWithdraw,
+    /// Creates a new stream with the same sender, receiver and rate as an
+    /// existing one, starting now and funded with `initial_balance`.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Template stream account, owned by this program
+    /// 1. `[signer, writable]` New, zeroed keypair stream account, owned by
+    ///    this program
+    /// 2. `[signer]` Sender of the template stream
+    Clone { initial_balance: u64 },
+    /// Records that the receiver accepted the final payout of a terminated stream.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[signer]` Receiver of the stream
+    AcknowledgeSettlement,
+    /// Records the available balance and time in the stream's checkpoint
+    /// ring buffer, evicting the oldest entry once it is full.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[signer]` Sender or receiver of the stream
+    Checkpoint,
+    /// Settles two linear streams between the same parties and folds the
+    /// second into the first, restarting it with the combined balance and
+    /// flow rate from `StreamConfig::restart_time`, which both streams must
+    /// agree on. The second account is closed and its rent returned to the
+    /// sender.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream to keep, owned by this program
+    /// 1. `[writable]` Stream to close, owned by this program
+    /// 2. `[signer, writable]` Sender of both streams
+    MergeStreams,
+    /// Settles a linear stream and restarts it from
+    /// `StreamConfig::restart_time`, accruing `factor_bps` out of
+    /// `FULL_RATE_BPS` of its flow rate. Throttling back to `FULL_RATE_BPS`
+    /// resumes the full rate. Only allowed when the sender may terminate, as
+    /// it is a partial cancellation.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[signer]` Sender of the stream
+    Throttle { factor_bps: u16 },
+    /// Returns when the stream runs dry via return data, as a little-endian
+    /// `i64` timestamp. `i64::MAX` means it never does: a terminated stream,
+    /// a zero or fully throttled rate, or a decay that never covers the balance.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Stream account, owned by this program
+    Projection,
+    /// Removes `amount` of the balance that has not streamed yet, shortening
+    /// the stream without terminating it. Amounts already payable to the
+    /// receiver and the reserve are never taken. Only allowed when the sender
+    /// may terminate.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[signer]` Sender of the stream
+    Clawback { amount: u64 },
+    /// Returns via return data a single byte, 1 if `amount` is currently
+    /// payable to the receiver and 0 otherwise, without writing to the
+    /// stream account.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Stream account, owned by this program
+    CanWithdraw { amount: u64 },
+    /// Lets the receiver refuse a stream outright. Unlike Terminate nothing is
+    /// settled to the receiver; the account is closed and everything in it
+    /// returned to the sender. A terminated stream can no longer be declined,
+    /// so its settlement record is kept.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[writable]` Sender of the stream, receives the account's lamports
+    /// 2. `[signer]` Receiver of the stream
+    DeclineStream,
+    /// Returns the stream's `StreamSummary` as borsh via return data, without
+    /// writing to the stream account.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Stream account, owned by this program
+    GetState,
+    /// Changes the reserved balance to `amount`. The reserve cannot exceed the
+    /// balance, and cannot grow into what is already payable to the receiver.
+    /// Raising it shortens the stream, so that needs the sender to be allowed
+    /// to terminate; lowering it is always allowed.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Stream account, owned by this program
+    /// 1. `[signer]` Sender of the stream
+    SetReserve { amount: u64 },
+}
+
+impl StreamInstruction {
+    /// Leading byte of the serialized instruction. New variants are only ever
+    /// appended, so these values are part of the wire format.
+    pub fn discriminant(&self) -> u8 {
+        match self {
+            StreamInstruction::Initialize { .. } => 0,
+            StreamInstruction::Terminate => 1,
+            StreamInstruction::PreviewWithdraw => 2,
+            StreamInstruction::Clone { .. } => 3,
+            StreamInstruction::AcknowledgeSettlement => 4,
+            StreamInstruction::Checkpoint => 5,
+            StreamInstruction::MergeStreams => 6,
+            StreamInstruction::Throttle { .. } => 7,
+            StreamInstruction::Projection => 8,
+            StreamInstruction::Clawback { .. } => 9,
+            StreamInstruction::CanWithdraw { .. } => 10,
+            StreamInstruction::DeclineStream => 11,
+            StreamInstruction::GetState => 12,
+            StreamInstruction::SetReserve { .. } => 13,
+        }
+    }
 }
 
 impl StreamConfig {
+    /// Size to allocate for a stream account. Streams with a shorter curve
+    /// encoding leave unused bytes at the end.
+    pub const LEN: usize = 32
+        + 32
+        + 8
+        + 1
+        + 8
+        + 8
+        + 1
+        + 1
+        + 8
+        + 1
+        + 8
+        + CHECKPOINT_SLOTS * 16
+        + 8
+        + 1
+        + 1
+        + 2
+        + 1
+        + 8
+        + 8
+        + 8
+        + StreamCurve::MAX_LEN;
+
     pub fn initialize(
         sender: Pubkey,
         receiver: Pubkey,
         flow_rate: i64,
+        flow_period: FlowPeriod,
+        curve: StreamCurve,
         initial_balance: u64,
         start_time: i64,
     ) -> Self {
@@ -31,8 +417,1376 @@ impl StreamConfig {
             sender,
             receiver,
             flow_rate,
+            flow_period,
             static_balance: initial_balance,
             start_time,
+            terminated: false,
+            acknowledged: false,
+            acknowledged_at: 0,
+            cancel_policy: CancelPolicy::default(),
+            reserved_balance: 0,
+            checkpoint_ring: [Checkpoint::default(); CHECKPOINT_SLOTS],
+            checkpoints_written: 0,
+            account_kind: StreamAccountKind::Keypair,
+            bump: 0,
+            throttle_bps: FULL_RATE_BPS,
+            emit_events: true,
+            billing_period: 0,
+            created_at: start_time,
+            settled_balance: 0,
+            curve,
+        }
+    }
+
+    /// Program address and bump for the stream from `sender` to `receiver`.
+    pub fn find_address(program_id: &Pubkey, sender: &Pubkey, receiver: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[STREAM_SEED, sender.as_ref(), receiver.as_ref()],
+            program_id,
+        )
+    }
+
+    /// Checks that `key` is this stream's program address using the stored
+    /// bump, which is cheaper than deriving it again with `find_address`.
+    /// Keypair streams have no address to derive and always pass.
+    pub fn verify_address(&self, program_id: &Pubkey, key: &Pubkey) -> Result<(), ProgramError> {
+        if self.account_kind == StreamAccountKind::Keypair {
+            return Ok(());
+        }
+        let address = Pubkey::create_program_address(
+            &[
+                STREAM_SEED,
+                self.sender.as_ref(),
+                self.receiver.as_ref(),
+                &[self.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| StreamError::InvalidStreamAddress)?;
+        if address != *key {
+            return Err(StreamError::InvalidStreamAddress.into());
+        }
+        Ok(())
+    }
+
+    /// Reads a stream from account data, ignoring unused trailing bytes.
+    /// All-zero data, as left by a fresh or closed account, is not a stream
+    /// even though it would decode as one.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.iter().all(|byte| *byte == 0) {
+            return Err(StreamError::NotInitialized.into());
+        }
+        Ok(Self::deserialize(&mut &data[..])?)
+    }
+
+    /// Amount accrued since `start_time` as of `now`, regardless of the balance.
+    pub fn accrued(&self, now: i64) -> Result<u64, StreamError> {
+        // Fully throttled streams accrue nothing, even where the curve overflows
+        if self.throttle_bps == 0 {
+            return Ok(0);
+        }
+        let mut time_elapsed = now.saturating_sub(self.start_time);
+        if self.billing_period > 0 {
+            time_elapsed -= time_elapsed.rem_euclid(self.billing_period);
+        }
+        let streamed = self
+            .curve
+            .streamed(self.flow_rate, self.flow_period, time_elapsed)?;
+        if self.throttle_bps == FULL_RATE_BPS {
+            return Ok(streamed);
+        }
+        // Never larger than `streamed`, as `throttle_bps` is at most full rate
+        Ok((streamed as u128 * self.throttle_bps as u128 / FULL_RATE_BPS as u128) as u64)
+    }
+
+    /// Time a stream settled at `now` resumes accruing from. A stream that has
+    /// not started keeps its scheduled start. With a billing period it is the
+    /// start of the current period, which settlement has not credited yet, so
+    /// the receiver keeps the part of it already elapsed.
+    pub fn restart_time(&self, now: i64) -> i64 {
+        if now <= self.start_time {
+            self.start_time
+        } else if self.billing_period > 0 {
+            now - now.saturating_sub(self.start_time) % self.billing_period
+        } else {
+            now
+        }
+    }
+
+    /// Part of `static_balance` that can stream to the receiver.
+    pub fn streamable_balance(&self) -> u64 {
+        self.static_balance.saturating_sub(self.reserved_balance)
+    }
+
+    /// Amount streamed to the receiver as of `now`, capped at the streamable balance.
+    pub fn available_balance(&self, now: i64) -> Result<u64, StreamError> {
+        if self.terminated {
+            return Ok(0);
+        }
+        // An accrual overflow is larger than any balance
+        let accrued = match self.accrued(now) {
+            Err(StreamError::AccrualOverflow) => u64::MAX,
+            accrued => accrued?,
+        };
+        let (paid, _) = apply_accrual(self.streamable_balance(), accrued);
+        Ok(paid)
+    }
+
+    /// Timestamp at which accrual first covers the streamable balance, or `None` if
+    /// the stream never runs dry (zero rate, or a decay curve whose total is
+    /// below the balance). For an already depleted stream this is the moment
+    /// it ran out, which may be before `now`.
+    pub fn depletion_time(&self, now: i64) -> Option<i64> {
+        // An accrual overflow is larger than any balance
+        let streamable = self.streamable_balance();
+        let depleted_at = |t: i64| {
+            self.accrued(t)
+                .map_or(true, |accrued| accrued >= streamable)
+        };
+
+        if streamable == 0 {
+            return Some(self.start_time);
+        }
+
+        // Find lo < depletion <= hi, then bisect
+        let (mut lo, mut hi) = if now > self.start_time && depleted_at(now) {
+            (self.start_time, now)
+        } else if depleted_at(i64::MAX) {
+            (now.max(self.start_time), i64::MAX)
+        } else {
+            return None;
+        };
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if depleted_at(mid) {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Some(hi)
+    }
+
+    /// Share of the receiver's total, everything already settled plus the
+    /// streamable balance, that has streamed as of `now`, from 0 to 100.
+    /// Counting `settled_balance` keeps the share steady across a Throttle
+    /// or MergeStreams restart. `None` once terminated, as nothing streams
+    /// any more.
+    pub fn percent_streamed(&self, now: i64) -> Option<u8> {
+        if self.terminated {
+            return None;
+        }
+        let streamable = self.streamable_balance();
+        let total = self.settled_balance as u128 + streamable as u128;
+        if total == 0 {
+            return Some(100);
+        }
+        // An accrual overflow is larger than any balance
+        let streamed = self
+            .accrued(now)
+            .map_or(streamable, |accrued| accrued.min(streamable));
+        Some(((self.settled_balance as u128 + streamed as u128) * 100 / total) as u8)
+    }
+
+    /// Rate accruing at `now`, per `flow_period`. Zero before the start, once
+    /// terminated, paused or depleted. Otherwise it is the curve's rate scaled
+    /// by `throttle_bps`: `flow_rate` for a linear stream, `flow_rate` halved
+    /// once per elapsed half-life for a decay, and for a drip its `chunk` per
+    /// `interval` averaged over a `flow_period`. Billing periods only delay
+    /// crediting and do not change the rate.
+    pub fn current_flow_rate(&self, now: i64) -> i64 {
+        if now < self.start_time || self.status(now) != StreamStatus::Active {
+            return 0;
+        }
+        let rate = match self.curve {
+            StreamCurve::Linear => self.flow_rate,
+            StreamCurve::ExponentialDecay { half_life } => {
+                let halvings = now
+                    .saturating_sub(self.start_time)
+                    .checked_div(half_life)
+                    .unwrap_or(0);
+                u32::try_from(halvings)
+                    .ok()
+                    .and_then(|halvings| self.flow_rate.checked_shr(halvings))
+                    .unwrap_or(0)
+            }
+            StreamCurve::Drip { chunk, interval } => {
+                let per_period = chunk as i128 * self.flow_period.seconds() as i128;
+                i64::try_from(per_period.checked_div(interval as i128).unwrap_or(0))
+                    .unwrap_or(i64::MAX)
+            }
+        };
+        (rate as i128 * self.throttle_bps as i128 / FULL_RATE_BPS as i128) as i64
+    }
+
+    /// Stores a checkpoint of the available balance as of `now`.
+    pub fn record_checkpoint(&mut self, now: i64) -> Result<(), StreamError> {
+        let slot = (self.checkpoints_written % CHECKPOINT_SLOTS as u64) as usize;
+        self.checkpoint_ring[slot] = Checkpoint {
+            timestamp: now,
+            available: self.available_balance(now)?,
+        };
+        self.checkpoints_written = self.checkpoints_written.saturating_add(1);
+        Ok(())
+    }
+
+    /// Retained checkpoints, oldest first.
+    pub fn checkpoints(&self) -> Vec<Checkpoint> {
+        let retained = self.checkpoints_written.min(CHECKPOINT_SLOTS as u64) as usize;
+        let next = (self.checkpoints_written % CHECKPOINT_SLOTS as u64) as usize;
+        (0..retained)
+            .map(|i| {
+                self.checkpoint_ring[(next + CHECKPOINT_SLOTS - retained + i) % CHECKPOINT_SLOTS]
+            })
+            .collect()
+    }
+
+    /// `Debug` output extended with the accrued and available amounts and
+    /// the stream status as of `now`.
+    pub fn debug_snapshot(&self, now: i64) -> String {
+        format!(
+            "{:?} at {}: accrued={:?} available={:?} status={}",
+            self,
+            now,
+            self.accrued(now),
+            self.available_balance(now),
+            self.status(now)
+        )
+    }
+
+    /// Summary for GetState as of `now`.
+    pub fn summary(&self, now: i64) -> Result<StreamSummary, StreamError> {
+        let payable = self.available_balance(now)?;
+        Ok(StreamSummary {
+            receiver: self.receiver,
+            payable,
+            remaining: self.static_balance - payable,
+            flow_rate: self.flow_rate,
+            flow_period: self.flow_period,
+            status: self.status(now),
+            current_flow_rate: self.current_flow_rate(now),
+            // Settlement overwrote the balance the projection is based on
+            depletion_time: self.depletion_time(now).filter(|_| !self.terminated),
+            percent_streamed: self.percent_streamed(now),
+        })
+    }
+
+    /// Lifecycle state as of `now`.
+    pub fn status(&self, now: i64) -> StreamStatus {
+        let streamable = self.streamable_balance();
+        if self.acknowledged {
+            StreamStatus::Acknowledged
+        } else if self.terminated {
+            StreamStatus::Terminated
+        } else if self
+            .accrued(now)
+            .map_or(true, |accrued| accrued >= streamable)
+        {
+            StreamStatus::Depleted
+        } else if self.throttle_bps == 0 {
+            StreamStatus::Paused
+        } else {
+            StreamStatus::Active
+        }
+    }
+}
+
+/// Lifecycle state of a stream, see `StreamConfig::status`. Part of the
+/// `StreamSummary` layout, so variants are only ever appended.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    Active,
+    /// Throttled to zero
+    Paused,
+    /// Everything streamable has accrued
+    Depleted,
+    Terminated,
+    /// Terminated and the receiver acknowledged the payout
+    Acknowledged,
+}
+
+impl fmt::Display for StreamStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StreamStatus::Active => "active",
+            StreamStatus::Paused => "paused",
+            StreamStatus::Depleted => "depleted",
+            StreamStatus::Terminated => "terminated",
+            StreamStatus::Acknowledged => "acknowledged",
+        })
+    }
+}
+
+/// Compact view of a stream returned by GetState for other programs. Its
+/// layout is independent of `StreamConfig` and only ever grows at the end, so
+/// composers keep decoding it as the account layout changes.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StreamSummary {
+    pub receiver: Pubkey,
+    /// Amount currently payable to the receiver
+    pub payable: u64,
+    /// Balance left after the payable amount, including the reserve
+    pub remaining: u64,
+    pub flow_rate: i64,
+    pub flow_period: FlowPeriod,
+    pub status: StreamStatus,
+    /// See `StreamConfig::current_flow_rate`
+    pub current_flow_rate: i64,
+    /// See `StreamConfig::depletion_time`, `None` once terminated
+    pub depletion_time: Option<i64>,
+    /// See `StreamConfig::percent_streamed`
+    pub percent_streamed: Option<u8>,
+}
+
+/// Live view of a stream computed off-chain from fetched account data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSnapshot {
+    pub status: StreamStatus,
+    pub available: Result<u64, StreamError>,
+    /// `None` for streams that never run dry or are terminated
+    pub depletion_time: Option<i64>,
+    pub percent_streamed: Option<u8>,
+}
+
+/// Snapshots of every stream in `configs` as of `now`, in the same order.
+pub fn snapshot_many(configs: &[StreamConfig], now: i64) -> Vec<StreamSnapshot> {
+    configs
+        .iter()
+        .map(|config| StreamSnapshot {
+            status: config.status(now),
+            available: config.available_balance(now),
+            // Settlement overwrote the balance the projection is based on
+            depletion_time: config.depletion_time(now).filter(|_| !config.terminated),
+            percent_streamed: config.percent_streamed(now),
+        })
+        .collect()
+}
+
+/// Checks that a stream changed consistently across one instruction executed
+/// `elapsed` seconds after `before.start_time`, for integrators testing their
+/// own CPI flows. There is no withdrawn total in this program, so accounting
+/// is checked against what was payable to the receiver at that time.
+pub fn verify_post_state(
+    before: &StreamConfig,
+    after: &StreamConfig,
+    elapsed: i64,
+) -> Result<(), String> {
+    let now = before.start_time.saturating_add(elapsed);
+
+    if after.sender != before.sender || after.receiver != before.receiver {
+        return Err("sender or receiver changed".to_string());
+    }
+    if after.flow_period != before.flow_period
+        || after.curve != before.curve
+        || after.billing_period != before.billing_period
+    {
+        return Err("flow period, curve or billing period changed".to_string());
+    }
+    if after.account_kind != before.account_kind || after.bump != before.bump {
+        return Err("stream address changed".to_string());
+    }
+    if after.created_at != before.created_at {
+        return Err("creation time changed".to_string());
+    }
+    if before.terminated && !after.terminated {
+        return Err("terminated stream was revived".to_string());
+    }
+    if before.acknowledged && !after.acknowledged {
+        return Err("acknowledgement was cleared".to_string());
+    }
+    if after.acknowledged && !after.terminated {
+        return Err("acknowledged stream is not terminated".to_string());
+    }
+    if after.reserved_balance > after.static_balance {
+        return Err(format!(
+            "reserve {} exceeds balance {}",
+            after.reserved_balance, after.static_balance
+        ));
+    }
+    if after.throttle_bps > FULL_RATE_BPS {
+        return Err(format!("throttle {} exceeds full rate", after.throttle_bps));
+    }
+    if after.checkpoints_written < before.checkpoints_written {
+        return Err("checkpoint count went backwards".to_string());
+    }
+    if after.settled_balance < before.settled_balance {
+        return Err("settled total went backwards".to_string());
+    }
+    if after.start_time < before.start_time {
+        return Err("start time went backwards".to_string());
+    }
+
+    let owed = before.available_balance(now).map_err(|e| e.to_string())?;
+    if after.terminated && !before.terminated {
+        // Terminate deducts exactly what was owed
+        if before.static_balance.checked_sub(after.static_balance) != Some(owed) {
+            return Err(format!(
+                "termination settled {} instead of {}",
+                before.static_balance.saturating_sub(after.static_balance),
+                owed
+            ));
+        }
+    } else if after.start_time != before.start_time {
+        // Throttle and MergeStreams settle and restart, keeping the billing phase
+        let restart_time = before.restart_time(now);
+        if after.start_time != restart_time {
+            return Err(format!(
+                "stream restarted at {} rather than {}",
+                after.start_time, restart_time
+            ));
+        }
+        // A throttle only settles what was owed; a merge also adds the other
+        // stream's balance and rate, which never lowers either
+        let settled = before.static_balance - owed;
+        if after.flow_rate == before.flow_rate {
+            if after.static_balance != settled {
+                return Err(format!(
+                    "restart left balance {} instead of {}",
+                    after.static_balance, settled
+                ));
+            }
+        } else if after.flow_rate < before.flow_rate || after.static_balance < settled {
+            return Err("merge lowered the flow rate or balance".to_string());
+        }
+    } else {
+        // Without a settlement the receiver keeps everything owed
+        let still_owed = after.available_balance(now).map_err(|e| e.to_string())?;
+        if still_owed < owed {
+            return Err(format!(
+                "payable dropped from {} to {} without settlement",
+                owed, still_owed
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Offsets of the fixed-size fields that precede `curve` in a serialized stream
+const SENDER_OFFSET: usize = 0;
+const RECEIVER_OFFSET: usize = 32;
+const FLOW_RATE_OFFSET: usize = 64;
+const STATIC_BALANCE_OFFSET: usize = 73;
+const START_TIME_OFFSET: usize = 81;
+const TERMINATED_OFFSET: usize = 89;
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ProgramError> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+/// Reads `sender` from stream account data without deserializing the rest.
+pub fn read_sender(data: &[u8]) -> Result<Pubkey, ProgramError> {
+    read_bytes(data, SENDER_OFFSET).map(Pubkey::new_from_array)
+}
+
+/// Reads `receiver` from stream account data without deserializing the rest.
+pub fn read_receiver(data: &[u8]) -> Result<Pubkey, ProgramError> {
+    read_bytes(data, RECEIVER_OFFSET).map(Pubkey::new_from_array)
+}
+
+/// Reads `flow_rate` from stream account data without deserializing the rest.
+pub fn read_flow_rate(data: &[u8]) -> Result<i64, ProgramError> {
+    read_bytes(data, FLOW_RATE_OFFSET).map(i64::from_le_bytes)
+}
+
+/// Reads `static_balance` from stream account data without deserializing the rest.
+pub fn read_static_balance(data: &[u8]) -> Result<u64, ProgramError> {
+    read_bytes(data, STATIC_BALANCE_OFFSET).map(u64::from_le_bytes)
+}
+
+/// Reads `start_time` from stream account data without deserializing the rest.
+pub fn read_start_time(data: &[u8]) -> Result<i64, ProgramError> {
+    read_bytes(data, START_TIME_OFFSET).map(i64::from_le_bytes)
+}
+
+/// Reads `terminated` from stream account data without deserializing the rest.
+pub fn read_terminated(data: &[u8]) -> Result<bool, ProgramError> {
+    match read_bytes::<1>(data, TERMINATED_OFFSET)? {
+        [0] => Ok(false),
+        [1] => Ok(true),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flow_periods_accrue_equally_over_a_day() {
+        let one_day = 86400;
+        let periods = [
+            (FlowPeriod::Second, 1),
+            (FlowPeriod::Minute, 60),
+            (FlowPeriod::Hour, 3600),
+            (FlowPeriod::Day, 86400),
+        ];
+
+        for (flow_period, flow_rate) in periods {
+            let stream = StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                flow_rate,
+                flow_period,
+                StreamCurve::Linear,
+                u64::MAX,
+                1000,
+            );
+            assert_eq!(stream.available_balance(1000 + one_day).unwrap(), 86400);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_flow_period_truncates_partial_units() {
+        let stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Hour,
+            StreamCurve::Linear,
+            u64::MAX,
+            0,
+        );
+
+        // 10 per hour pays one unit every 360 seconds
+        assert_eq!(stream.available_balance(359).unwrap(), 0);
+        assert_eq!(stream.available_balance(360).unwrap(), 1);
+        assert_eq!(stream.available_balance(1800).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_flow_period_accrual_avoids_intermediate_overflow() {
+        let ten_years = 86400 * 3650;
+        let flow_rate = 10_000_000_000_000;
+
+        // flow_rate * elapsed alone would overflow u64
+        assert!(checked_accrual(flow_rate, ten_years).is_err());
+        assert_eq!(
+            FlowPeriod::Day.accrual(flow_rate, ten_years + 43200),
+            Ok(3650 * flow_rate as u64 + flow_rate as u64 / 2)
+        );
+        assert_eq!(
+            FlowPeriod::Second.accrual(flow_rate, ten_years),
+            Err(StreamError::AccrualOverflow)
+        );
+    }
+
+    #[test]
+    fn test_exponential_decay_front_loads_payout() {
+        let half_life = 3600;
+        let linear = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            8,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            u64::MAX,
+            0,
+        );
+        let decay = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            8,
+            FlowPeriod::Second,
+            StreamCurve::ExponentialDecay { half_life },
+            u64::MAX,
+            0,
+        );
+
+        // Both pay the same during the first half-life
+        assert_eq!(linear.available_balance(1800).unwrap(), 8 * 1800);
+        assert_eq!(decay.available_balance(1800).unwrap(), 8 * 1800);
+
+        // Then the decayed rate halves each half-life
+        assert_eq!(linear.available_balance(2 * half_life).unwrap(), 8 * 7200);
+        assert_eq!(
+            decay.available_balance(2 * half_life).unwrap(),
+            8 * 3600 + 4 * 3600
+        );
+        assert_eq!(
+            decay.available_balance(3 * half_life).unwrap(),
+            8 * 3600 + 4 * 3600 + 2 * 3600
+        );
+
+        // The total converges to twice the first half-life's payout
+        assert!(decay.available_balance(1000 * half_life).unwrap() <= 2 * 8 * 3600);
+        assert!(decay.available_balance(1000 * half_life).unwrap() > 2 * 8 * 3600 - 8);
+    }
+
+    #[test]
+    fn test_exponential_decay_respects_flow_period_and_balance() {
+        let decay = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            60,
+            FlowPeriod::Minute,
+            StreamCurve::ExponentialDecay { half_life: 100 },
+            150,
+            0,
+        );
+
+        assert_eq!(decay.available_balance(100).unwrap(), 100);
+        assert_eq!(decay.available_balance(200).unwrap(), 150);
+        assert_eq!(decay.available_balance(i64::MAX).unwrap(), 150);
+    }
+
+    #[test]
+    fn test_curve_validation() {
+        assert_eq!(StreamCurve::Linear.validate(1, FlowPeriod::Second), Ok(()));
+        assert_eq!(
+            StreamCurve::ExponentialDecay { half_life: 1 }.validate(1, FlowPeriod::Second),
+            Ok(())
+        );
+        assert_eq!(
+            StreamCurve::ExponentialDecay { half_life: 0 }.validate(1, FlowPeriod::Second),
+            Err(StreamError::InvalidCurve)
+        );
+        assert_eq!(
+            StreamCurve::ExponentialDecay { half_life: -5 }.validate(1, FlowPeriod::Second),
+            Err(StreamError::InvalidCurve)
+        );
+        assert_eq!(
+            StreamCurve::Drip {
+                chunk: 0,
+                interval: 1
+            }
+            .validate(1, FlowPeriod::Second),
+            Ok(())
+        );
+        assert_eq!(
+            StreamCurve::Drip {
+                chunk: 100,
+                interval: 0
+            }
+            .validate(1, FlowPeriod::Second),
+            Err(StreamError::InvalidCurve)
+        );
+
+        // The first half-life alone would pay more than a u64 holds
+        let long_decay = StreamCurve::ExponentialDecay {
+            half_life: 1_000_000_000_000,
+        };
+        assert_eq!(
+            long_decay.validate(100_000_000, FlowPeriod::Second),
+            Err(StreamError::InvalidCurve)
+        );
+        assert_eq!(long_decay.validate(1_000_000, FlowPeriod::Second), Ok(()));
+
+        // Configs that skipped validation error instead of dividing by zero
+        let unvalidated = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1,
+            FlowPeriod::Second,
+            StreamCurve::ExponentialDecay { half_life: 0 },
+            1000,
+            0,
+        );
+        assert_eq!(
+            unvalidated.available_balance(10),
+            Err(StreamError::InvalidCurve)
+        );
+        assert_eq!(
+            snapshot_many(&[unvalidated], 10)[0].available,
+            Err(StreamError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn test_drip_releases_whole_chunks() {
+        let stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1,
+            FlowPeriod::Second,
+            StreamCurve::Drip {
+                chunk: 100,
+                interval: 3600,
+            },
+            250,
+            1000,
+        );
+
+        assert_eq!(stream.available_balance(999), Ok(0));
+        assert_eq!(stream.available_balance(1000 + 3599), Ok(0));
+        assert_eq!(stream.available_balance(1000 + 3600), Ok(100));
+        assert_eq!(stream.available_balance(1000 + 7199), Ok(100));
+        assert_eq!(stream.available_balance(1000 + 7200), Ok(200));
+        // The last drip is capped at what is left
+        assert_eq!(stream.available_balance(1000 + 10800), Ok(250));
+        assert_eq!(stream.depletion_time(1000), Some(1000 + 10800));
+
+        // An unvalidated zero interval errors instead of dividing by zero
+        let unvalidated = StreamConfig {
+            curve: StreamCurve::Drip {
+                chunk: 100,
+                interval: 0,
+            },
+            ..stream
+        };
+        assert_eq!(
+            unvalidated.available_balance(1000 + 3600),
+            Err(StreamError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn test_instruction_round_trip() {
+        let instructions = [
+            StreamInstruction::Initialize {
+                flow_rate: 100,
+                flow_period: FlowPeriod::Second,
+                curve: StreamCurve::Linear,
+                initial_balance: 1000,
+                account_kind: StreamAccountKind::Keypair,
+                cancel_policy: CancelPolicy::Either,
+                reserved_balance: 0,
+                snap_start_to_midnight: false,
+                emit_events: true,
+                billing_period: 0,
+            },
+            StreamInstruction::Initialize {
+                flow_rate: -1,
+                flow_period: FlowPeriod::Day,
+                curve: StreamCurve::ExponentialDecay {
+                    half_life: i64::MAX,
+                },
+                initial_balance: u64::MAX,
+                account_kind: StreamAccountKind::ProgramAddress,
+                cancel_policy: CancelPolicy::None,
+                reserved_balance: 0,
+                snap_start_to_midnight: true,
+                emit_events: false,
+                billing_period: i64::MAX,
+            },
+            StreamInstruction::Terminate,
+            StreamInstruction::PreviewWithdraw,
+            StreamInstruction::Clone {
+                initial_balance: 5000,
+            },
+            StreamInstruction::AcknowledgeSettlement,
+            StreamInstruction::Checkpoint,
+            StreamInstruction::MergeStreams,
+            StreamInstruction::Throttle { factor_bps: 5000 },
+            StreamInstruction::Projection,
+            StreamInstruction::Clawback { amount: 250 },
+            StreamInstruction::CanWithdraw { amount: u64::MAX },
+            StreamInstruction::DeclineStream,
+            StreamInstruction::GetState,
+            StreamInstruction::SetReserve { amount: 0 },
+        ];
+
+        for instruction in instructions {
+            let data = borsh::to_vec(&instruction).unwrap();
+            assert_eq!(
+                StreamInstruction::try_from_slice(&data).unwrap(),
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn test_depletion_time() {
+        let stream = |flow_rate, flow_period, curve, balance| {
+            StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                flow_rate,
+                flow_period,
+                curve,
+                balance,
+                1000,
+            )
+        };
+
+        // 1000 at 100 per second runs out after 10 seconds
+        let linear = stream(100, FlowPeriod::Second, StreamCurve::Linear, 1000);
+        assert_eq!(linear.depletion_time(1000), Some(1010));
+        assert_eq!(linear.depletion_time(1005), Some(1010));
+
+        // A partial final unit still has to accrue
+        let uneven = stream(3, FlowPeriod::Second, StreamCurve::Linear, 10);
+        assert_eq!(uneven.depletion_time(1000), Some(1004));
+
+        let hourly = stream(10, FlowPeriod::Hour, StreamCurve::Linear, 25);
+        assert_eq!(hourly.depletion_time(1000), Some(1000 + 9000));
+
+        // Already depleted streams report when they ran out
+        assert_eq!(linear.depletion_time(5000), Some(1010));
+        let empty = stream(100, FlowPeriod::Second, StreamCurve::Linear, 0);
+        assert_eq!(empty.depletion_time(5000), Some(1000));
+    }
+
+    #[test]
+    fn test_depletion_time_never_depletes() {
+        let zero_rate = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            0,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            1000,
+        );
+        assert_eq!(zero_rate.depletion_time(1000), None);
+
+        // Decay pays 8 + 4 + 2 + 1 = 15 in total, short of the balance
+        let decay = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            8,
+            FlowPeriod::Second,
+            StreamCurve::ExponentialDecay { half_life: 1 },
+            16,
+            1000,
+        );
+        assert_eq!(decay.depletion_time(1000), None);
+
+        let funded_decay = StreamConfig {
+            static_balance: 14,
+            ..decay
+        };
+        assert_eq!(funded_decay.depletion_time(1000), Some(1003));
+    }
+
+    #[test]
+    fn test_debug_snapshot() {
+        let stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            0,
+        );
+
+        let snapshot = stream.debug_snapshot(30);
+        assert!(snapshot.starts_with(&format!("{:?}", stream)));
+        assert!(snapshot.contains("accrued=Ok(300)"));
+        assert!(snapshot.contains("available=Ok(300)"));
+        assert!(snapshot.contains("status=active"));
+
+        assert!(stream.debug_snapshot(200).contains("status=depleted"));
+
+        let terminated = StreamConfig {
+            terminated: true,
+            ..stream
+        };
+        let snapshot = terminated.debug_snapshot(30);
+        assert!(snapshot.contains("available=Ok(0)"));
+        assert!(snapshot.contains("status=terminated"));
+    }
+
+    #[test]
+    fn test_percent_streamed() {
+        let stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            100,
+        );
+
+        assert_eq!(stream.percent_streamed(50), Some(0));
+        assert_eq!(stream.percent_streamed(100), Some(0));
+        assert_eq!(stream.percent_streamed(125), Some(25));
+        // Rounds down
+        assert_eq!(stream.percent_streamed(199), Some(99));
+        assert_eq!(stream.percent_streamed(200), Some(100));
+        assert_eq!(stream.percent_streamed(i64::MAX), Some(100));
+
+        let empty = StreamConfig {
+            static_balance: 0,
+            ..stream.clone()
+        };
+        assert_eq!(empty.percent_streamed(100), Some(100));
+
+        // Settled amounts count towards both the streamed share and the total
+        let restarted = StreamConfig {
+            static_balance: 600,
+            settled_balance: 400,
+            ..stream.clone()
+        };
+        assert_eq!(restarted.percent_streamed(100), Some(40));
+        assert_eq!(restarted.percent_streamed(130), Some(70));
+
+        let terminated = StreamConfig {
+            terminated: true,
+            ..stream
+        };
+        assert_eq!(terminated.percent_streamed(125), None);
+    }
+
+    #[test]
+    fn test_field_readers_match_deserialization() {
+        let stream = StreamConfig {
+            terminated: true,
+            ..StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                -1,
+                FlowPeriod::Hour,
+                StreamCurve::ExponentialDecay { half_life: 60 },
+                u64::MAX - 1,
+                i64::MIN + 1,
+            )
+        };
+        let mut data = vec![0; StreamConfig::LEN];
+        stream.serialize(&mut &mut data[..]).unwrap();
+
+        assert_eq!(read_sender(&data), Ok(stream.sender));
+        assert_eq!(read_receiver(&data), Ok(stream.receiver));
+        assert_eq!(read_flow_rate(&data), Ok(stream.flow_rate));
+        assert_eq!(read_static_balance(&data), Ok(stream.static_balance));
+        assert_eq!(read_start_time(&data), Ok(stream.start_time));
+        assert_eq!(read_terminated(&data), Ok(stream.terminated));
+
+        // Truncated data is rejected rather than read past
+        assert_eq!(
+            read_start_time(&data[..START_TIME_OFFSET + 7]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        data[TERMINATED_OFFSET] = 2;
+        assert_eq!(
+            read_terminated(&data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_accrual_never_touches_reserve() {
+        let stream = StreamConfig {
+            reserved_balance: 400,
+            ..StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                10,
+                FlowPeriod::Second,
+                StreamCurve::Linear,
+                1000,
+                0,
+            )
+        };
+
+        assert_eq!(stream.streamable_balance(), 600);
+        assert_eq!(stream.available_balance(30), Ok(300));
+        assert_eq!(stream.available_balance(60), Ok(600));
+        assert_eq!(stream.available_balance(1_000_000), Ok(600));
+        assert_eq!(stream.depletion_time(0), Some(60));
+        assert_eq!(stream.percent_streamed(30), Some(50));
+        assert!(stream.debug_snapshot(60).contains("status=depleted"));
+
+        let all_reserved = StreamConfig {
+            reserved_balance: 1000,
+            ..stream
+        };
+        assert_eq!(all_reserved.available_balance(30), Ok(0));
+        assert_eq!(all_reserved.depletion_time(30), Some(0));
+    }
+
+    #[test]
+    fn test_checkpoints_evict_oldest() {
+        let mut stream = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            0,
+        );
+        assert_eq!(stream.checkpoints(), vec![]);
+
+        for now in [10, 20, 30] {
+            stream.record_checkpoint(now).unwrap();
+        }
+        let timestamps = |stream: &StreamConfig| -> Vec<i64> {
+            stream.checkpoints().iter().map(|c| c.timestamp).collect()
+        };
+        assert_eq!(timestamps(&stream), vec![10, 20, 30]);
+        assert_eq!(stream.checkpoints()[1].available, 20);
+
+        for now in [40, 50, 60] {
+            stream.record_checkpoint(now).unwrap();
+        }
+        assert_eq!(stream.checkpoints_written, 6);
+        assert_eq!(timestamps(&stream), vec![30, 40, 50, 60]);
+        assert_eq!(
+            stream.checkpoints()[3],
+            Checkpoint {
+                timestamp: 60,
+                available: 60,
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_address_with_stored_bump() {
+        let program_id = Pubkey::new_unique();
+        let sender = Pubkey::new_unique();
+        let receiver = Pubkey::new_unique();
+        let (address, bump) = StreamConfig::find_address(&program_id, &sender, &receiver);
+
+        let stream = StreamConfig {
+            account_kind: StreamAccountKind::ProgramAddress,
+            bump,
+            ..StreamConfig::initialize(
+                sender,
+                receiver,
+                1,
+                FlowPeriod::Second,
+                StreamCurve::Linear,
+                1000,
+                0,
+            )
+        };
+        assert_eq!(stream.verify_address(&program_id, &address), Ok(()));
+        assert_eq!(
+            stream.verify_address(&program_id, &Pubkey::new_unique()),
+            Err(StreamError::InvalidStreamAddress.into())
+        );
+
+        let wrong_bump = StreamConfig {
+            bump: bump.wrapping_sub(1),
+            ..stream.clone()
+        };
+        assert_eq!(
+            wrong_bump.verify_address(&program_id, &address),
+            Err(StreamError::InvalidStreamAddress.into())
+        );
+
+        let keypair = StreamConfig {
+            account_kind: StreamAccountKind::Keypair,
+            ..stream
+        };
+        assert_eq!(keypair.verify_address(&program_id, &address), Ok(()));
+    }
+
+    #[test]
+    fn test_current_flow_rate() {
+        let plain = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            64,
+            FlowPeriod::Minute,
+            StreamCurve::Linear,
+            1_000_000,
+            1000,
+        );
+        assert_eq!(plain.current_flow_rate(999), 0);
+        assert_eq!(plain.current_flow_rate(1000), 64);
+        assert_eq!(plain.current_flow_rate(5000), 64);
+
+        let throttled = StreamConfig {
+            throttle_bps: 2500,
+            ..plain.clone()
+        };
+        assert_eq!(throttled.current_flow_rate(5000), 16);
+        let paused = StreamConfig {
+            throttle_bps: 0,
+            ..plain.clone()
+        };
+        assert_eq!(paused.current_flow_rate(5000), 0);
+        let terminated = StreamConfig {
+            terminated: true,
+            ..plain.clone()
+        };
+        assert_eq!(terminated.current_flow_rate(5000), 0);
+        let depleted = StreamConfig {
+            static_balance: 64,
+            ..plain.clone()
+        };
+        assert_eq!(depleted.current_flow_rate(1000 + 60), 0);
+
+        // The rate halves at every half-life boundary
+        let decaying = StreamConfig {
+            curve: StreamCurve::ExponentialDecay { half_life: 600 },
+            ..plain.clone()
+        };
+        assert_eq!(decaying.current_flow_rate(1000 + 599), 64);
+        assert_eq!(decaying.current_flow_rate(1000 + 600), 32);
+        assert_eq!(decaying.current_flow_rate(1000 + 1800), 8);
+        assert_eq!(decaying.current_flow_rate(1000 + 600 * 7), 0);
+
+        // 100 every 2 hours averages 50 an hour, under a minute rounds down
+        let drip = StreamConfig {
+            flow_period: FlowPeriod::Hour,
+            curve: StreamCurve::Drip {
+                chunk: 100,
+                interval: 7200,
+            },
+            ..plain.clone()
+        };
+        assert_eq!(drip.current_flow_rate(1000), 50);
+        let per_minute = StreamConfig {
+            flow_period: FlowPeriod::Minute,
+            ..drip
+        };
+        assert_eq!(per_minute.current_flow_rate(1000), 0);
+    }
+
+    #[test]
+    fn test_throttled_accrual() {
+        let stream = StreamConfig {
+            throttle_bps: 5000,
+            ..StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                10,
+                FlowPeriod::Second,
+                StreamCurve::Linear,
+                1000,
+                0,
+            )
+        };
+
+        assert_eq!(stream.accrued(30), Ok(150));
+        // Rounds down
+        assert_eq!(stream.accrued(1), Ok(5));
+        assert_eq!(stream.depletion_time(0), Some(200));
+        assert_eq!(
+            StreamConfig {
+                throttle_bps: 0,
+                ..stream
+            }
+            .depletion_time(0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_instruction_discriminants_are_stable() {
+        let instructions = [
+            (
+                StreamInstruction::Initialize {
+                    flow_rate: 1,
+                    flow_period: FlowPeriod::Second,
+                    curve: StreamCurve::Linear,
+                    initial_balance: 1,
+                    account_kind: StreamAccountKind::Keypair,
+                    cancel_policy: CancelPolicy::Either,
+                    reserved_balance: 0,
+                    snap_start_to_midnight: false,
+                    emit_events: true,
+                    billing_period: 0,
+                },
+                0,
+            ),
+            (StreamInstruction::Terminate, 1),
+            (StreamInstruction::PreviewWithdraw, 2),
+            (StreamInstruction::Clone { initial_balance: 1 }, 3),
+            (StreamInstruction::AcknowledgeSettlement, 4),
+            (StreamInstruction::Checkpoint, 5),
+            (StreamInstruction::MergeStreams, 6),
+            (StreamInstruction::Throttle { factor_bps: 1 }, 7),
+            (StreamInstruction::Projection, 8),
+            (StreamInstruction::Clawback { amount: 1 }, 9),
+            (StreamInstruction::CanWithdraw { amount: 1 }, 10),
+            (StreamInstruction::DeclineStream, 11),
+            (StreamInstruction::GetState, 12),
+            (StreamInstruction::SetReserve { amount: 1 }, 13),
+        ];
+
+        for (instruction, discriminant) in instructions {
+            assert_eq!(instruction.discriminant(), discriminant);
+            // Must agree with the derived encoding
+            assert_eq!(borsh::to_vec(&instruction).unwrap()[0], discriminant);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_many() {
+        let active = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            10,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            0,
+        );
+        let depleted = StreamConfig {
+            static_balance: 300,
+            ..active.clone()
+        };
+        let paused = StreamConfig {
+            throttle_bps: 0,
+            ..active.clone()
+        };
+        let terminated = StreamConfig {
+            terminated: true,
+            ..active.clone()
+        };
+
+        let snapshots = snapshot_many(&[active, depleted, paused, terminated], 50);
+        assert_eq!(
+            snapshots,
+            vec![
+                StreamSnapshot {
+                    status: StreamStatus::Active,
+                    available: Ok(500),
+                    depletion_time: Some(100),
+                    percent_streamed: Some(50),
+                },
+                StreamSnapshot {
+                    status: StreamStatus::Depleted,
+                    available: Ok(300),
+                    depletion_time: Some(30),
+                    percent_streamed: Some(100),
+                },
+                StreamSnapshot {
+                    status: StreamStatus::Paused,
+                    available: Ok(0),
+                    depletion_time: None,
+                    percent_streamed: Some(0),
+                },
+                StreamSnapshot {
+                    status: StreamStatus::Terminated,
+                    available: Ok(0),
+                    depletion_time: None,
+                    percent_streamed: None,
+                },
+            ]
+        );
+        assert_eq!(snapshot_many(&[], 50), vec![]);
+    }
+
+    #[test]
+    fn test_verify_post_state() {
+        let before = StreamConfig::initialize(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1,
+            FlowPeriod::Second,
+            StreamCurve::Linear,
+            1000,
+            1000,
+        );
+
+        // 400 is owed after 400 seconds
+        let terminated = StreamConfig {
+            static_balance: 600,
+            terminated: true,
+            ..before.clone()
+        };
+        assert_eq!(verify_post_state(&before, &terminated, 400), Ok(()));
+        let clawed_back = StreamConfig {
+            static_balance: 500,
+            ..before.clone()
+        };
+        assert_eq!(verify_post_state(&before, &clawed_back, 400), Ok(()));
+        let throttled = StreamConfig {
+            static_balance: 600,
+            start_time: 1400,
+            throttle_bps: 5000,
+            ..before.clone()
+        };
+        assert_eq!(verify_post_state(&before, &throttled, 400), Ok(()));
+        assert_eq!(verify_post_state(&before, &before, 400), Ok(()));
+
+        // Mid-period, nothing is owed and the restart keeps the period's start
+        let billed = StreamConfig {
+            billing_period: 300,
+            ..before.clone()
+        };
+        let rebilled = StreamConfig {
+            start_time: 1300,
+            static_balance: 700,
+            throttle_bps: 5000,
+            ..billed.clone()
+        };
+        assert_eq!(verify_post_state(&billed, &rebilled, 400), Ok(()));
+        let rephased = StreamConfig {
+            start_time: 1400,
+            ..rebilled
+        };
+        assert!(verify_post_state(&billed, &rephased, 400).is_err());
+
+        let corrupted = [
+            StreamConfig {
+                receiver: Pubkey::new_unique(),
+                ..before.clone()
+            },
+            StreamConfig {
+                static_balance: 700,
+                terminated: true,
+                ..before.clone()
+            },
+            StreamConfig {
+                static_balance: 300,
+                ..before.clone()
+            },
+            StreamConfig {
+                reserved_balance: 1001,
+                ..before.clone()
+            },
+            StreamConfig {
+                acknowledged: true,
+                ..before.clone()
+            },
+            StreamConfig {
+                created_at: 1400,
+                ..before.clone()
+            },
+            StreamConfig {
+                start_time: 1200,
+                ..before.clone()
+            },
+            // A restart that did not deduct what was owed
+            StreamConfig {
+                start_time: 1400,
+                throttle_bps: 5000,
+                ..before.clone()
+            },
+            StreamConfig {
+                static_balance: 2000,
+                start_time: 1400,
+                ..before.clone()
+            },
+            StreamConfig {
+                static_balance: 600,
+                start_time: 1400,
+                flow_rate: 0,
+                ..before.clone()
+            },
+        ];
+        for after in corrupted {
+            assert!(
+                verify_post_state(&before, &after, 400).is_err(),
+                "{:?}",
+                after
+            );
+        }
+        assert_eq!(
+            verify_post_state(&terminated, &before, 400),
+            Err("terminated stream was revived".to_string())
+        );
+    }
+
+    #[test]
+    fn test_billing_period_credits_completed_periods() {
+        let stream = StreamConfig {
+            billing_period: 3600,
+            ..StreamConfig::initialize(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                1,
+                FlowPeriod::Second,
+                StreamCurve::Linear,
+                10_000,
+                0,
+            )
+        };
+
+        assert_eq!(stream.available_balance(3599), Ok(0));
+        assert_eq!(stream.available_balance(3600), Ok(3600));
+        assert_eq!(stream.available_balance(7199), Ok(3600));
+        assert_eq!(stream.available_balance(7200), Ok(7200));
+        assert_eq!(stream.available_balance(-1), Ok(0));
+        assert_eq!(stream.depletion_time(0), Some(10_800));
+    }
+}
@@ -0,0 +1,29 @@
+use crate::error::StreamError;
+use solana_program::{clock::Clock, msg, program_error::ProgramError, sysvar::Sysvar};
+
+/// Source of the current unix timestamp used for accrual.
+pub trait TimeSource {
+    fn now(&self) -> Result<i64, ProgramError>;
+}
+
+/// Reads the time from the `Clock` sysvar. This is what the entrypoint uses.
+pub struct SysvarClock;
+
+impl TimeSource for SysvarClock {
+    fn now(&self) -> Result<i64, ProgramError> {
+        let clock = Clock::get().map_err(|err| {
+            msg!("Clock sysvar unavailable: {}", err);
+            StreamError::ClockUnavailable
+        })?;
+        Ok(clock.unix_timestamp)
+    }
+}
+
+/// Always reports the same timestamp, for driving the processor off-chain.
+pub struct FixedTime(pub i64);
+
+impl TimeSource for FixedTime {
+    fn now(&self) -> Result<i64, ProgramError> {
+        Ok(self.0)
+    }
+}